@@ -1,38 +1,54 @@
-#[path = "../src/minhash.rs"]
-mod minhash;
-#[path = "../src/tsp.rs"]
-mod tsp;
-#[path = "../src/utils.rs"]
-mod utils;
+use std::io::Write;
 
-use std::path::Path;
-
-use crate::minhash::minhash;
-use crate::tsp::Tsp;
+use simsort::distances::TriangularDistances;
+use simsort::minhash::{minhash_stream, MinhashTarget};
 
 use criterion::BenchmarkId;
 use criterion::Throughput;
 use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use rand::rngs::StdRng;
+use rand::{RngCore, SeedableRng};
+use tempfile::NamedTempFile;
+
+fn build_linear_distances(size: usize) -> TriangularDistances<u8> {
+    let mut distances = TriangularDistances::blocked(size);
+    for i in 0..size {
+        for j in i..size {
+            distances.set(i, j, (j - i) as u8);
+        }
+    }
+    distances
+}
 
 fn build_distances(c: &mut Criterion) {
     let mut group = c.benchmark_group("bench_build_distances");
-    for size in [4096, 8196, 16384, 32768].iter() {
+    for size in [4096, 8192, 16384, 32768].iter() {
         group.throughput(Throughput::Elements((size * size) as u64));
         group.bench_with_input(BenchmarkId::from_parameter(size), size, |b, &size| {
-            b.iter(|| utils::build_linear_distances(size));
+            b.iter(|| build_linear_distances(size));
         });
     }
 }
 
+// A file of random bytes, which has no repeated shingles for minhash to skip
+fn random_file(count: usize) -> NamedTempFile {
+    let mut bytes = vec![0; count];
+    StdRng::seed_from_u64(count as u64).fill_bytes(&mut bytes);
+    let mut file = NamedTempFile::new().unwrap();
+    file.write_all(&bytes).unwrap();
+    file
+}
+
 fn bench_minhash(c: &mut Criterion) {
     let mut group = c.benchmark_group("bench_minhash");
 
     for count in [1024, 4096, 16384, 65536, 262144, 1048576, 4194304].iter() {
-        let file_path = Path::new("./testdata/").join(format!("rand{}", count));
+        let file = random_file(*count);
+        let target = MinhashTarget::File(file.path().to_path_buf());
 
         group.throughput(Throughput::Elements(*count as u64));
-        group.bench_with_input(BenchmarkId::from_parameter(count), &count, |b, &count| {
-            b.iter(|| minhash::minhash(&file_path));
+        group.bench_with_input(BenchmarkId::from_parameter(count), &target, |b, target| {
+            b.iter(|| black_box(minhash_stream(target).unwrap()));
         });
     }
 }
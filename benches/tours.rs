@@ -0,0 +1,183 @@
+use std::fs::read_to_string;
+
+use simsort::tour::{CyclicTour, Tour};
+use simsort::tsp::{Tsp, TspInstance};
+use simsort::twolevel::TwoLevelTour;
+use simsort::twoopt::optimize_twoopt_from_tour;
+use simsort::window::optimize_window;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+// Compares the array tour against the two-level list, on flips alone and on full 2-opt runs, and
+// times window refinement up to the batch sizes sparse-tsp allows
+
+const FLIPS: usize = 1000;
+const NEIGHBORS: usize = 8;
+
+fn read_berlin52() -> Vec<(f64, f64)> {
+    read_to_string("testdata/berlin52.tsp")
+        .unwrap()
+        .lines()
+        .filter_map(|line| {
+            let parts: Vec<&str> = line.split_whitespace().collect();
+            match parts.as_slice() {
+                [_, x, y] => Some((x.parse().ok()?, y.parse().ok()?)),
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+fn random_points(count: usize) -> Vec<(f64, f64)> {
+    let mut rng = StdRng::seed_from_u64(38);
+    (0..count)
+        .map(|_| (rng.gen_range(0.0..10000.0), rng.gen_range(0.0..10000.0)))
+        .collect()
+}
+
+// Euclidean instance that computes distances on demand, so it scales past a dense matrix
+struct Points {
+    points: Vec<(f64, f64)>,
+    neighbors: Vec<Vec<usize>>,
+}
+
+impl Points {
+    fn new(points: Vec<(f64, f64)>) -> Points {
+        let mut instance = Points {
+            neighbors: Vec::new(),
+            points,
+        };
+        instance.neighbors = (0..instance.points.len())
+            .map(|i| {
+                let mut others: Vec<usize> =
+                    (0..instance.points.len()).filter(|&j| j != i).collect();
+                others.select_nth_unstable_by_key(NEIGHBORS, |&j| instance.retrieve_distance(i, j));
+                others.truncate(NEIGHBORS);
+                others.sort_by_key(|&j| instance.retrieve_distance(i, j));
+                others
+            })
+            .collect();
+        instance
+    }
+}
+
+impl TspInstance for Points {
+    fn count(&self) -> usize {
+        self.points.len()
+    }
+
+    fn retrieve_distance(&self, t1: usize, t2: usize) -> u64 {
+        let (x1, y1) = self.points[t1];
+        let (x2, y2) = self.points[t2];
+        ((x1 - x2).powi(2) + (y1 - y2).powi(2)).sqrt().round() as u64
+    }
+
+    fn neighbors(&self, index: usize) -> &[usize] {
+        &self.neighbors[index]
+    }
+}
+
+fn bench_flips(c: &mut Criterion) {
+    let mut group = c.benchmark_group("flips");
+    for size in [52, 10000, 50000] {
+        let mut rng = StdRng::seed_from_u64(size as u64);
+        let flips: Vec<(usize, usize)> = (0..FLIPS)
+            .map(|_| (rng.gen_range(0..size), rng.gen_range(0..size)))
+            .collect();
+        group.bench_with_input(BenchmarkId::new("array", size), &flips, |b, flips| {
+            b.iter(|| apply_flips(Tour::new((0..size).collect()), flips))
+        });
+        group.bench_with_input(BenchmarkId::new("two-level", size), &flips, |b, flips| {
+            b.iter(|| apply_flips(TwoLevelTour::new((0..size).collect()), flips))
+        });
+    }
+}
+
+fn apply_flips<T: CyclicTour>(mut tour: T, flips: &[(usize, usize)]) -> usize {
+    for &(a, b) in flips {
+        tour.flip(a, b);
+    }
+    tour.next(0)
+}
+
+fn bench_twoopt(c: &mut Criterion) {
+    let mut group = c.benchmark_group("twoopt");
+    group.sample_size(10);
+
+    let berlin: Vec<(f64, f64)> = read_berlin52();
+    let count = berlin.len();
+    let distances: Vec<u32> = (0..count * count)
+        .map(|index| Points::distance(berlin[index / count], berlin[index % count]))
+        .collect();
+    let tsp = Tsp::new(distances, count);
+    group.bench_function(BenchmarkId::new("array", "berlin52"), |b| {
+        b.iter(|| optimize_twoopt_from_tour(&tsp, Tour::new((0..count).collect())))
+    });
+    group.bench_function(BenchmarkId::new("two-level", "berlin52"), |b| {
+        b.iter(|| optimize_twoopt_from_tour(&tsp, TwoLevelTour::new((0..count).collect())))
+    });
+
+    for size in [1000, 10000] {
+        let tsp = Points::new(random_points(size));
+        group.bench_function(BenchmarkId::new("array", size), |b| {
+            b.iter(|| optimize_twoopt_from_tour(&tsp, Tour::new((0..size).collect())))
+        });
+        group.bench_function(BenchmarkId::new("two-level", size), |b| {
+            b.iter(|| optimize_twoopt_from_tour(&tsp, TwoLevelTour::new((0..size).collect())))
+        });
+    }
+}
+
+fn bench_window(c: &mut Criterion) {
+    let mut group = c.benchmark_group("window");
+    group.sample_size(10);
+
+    // Past its file limit, window refinement returns the path untouched, so 500000 files should
+    // take no time at all
+    for size in [1000, 14000, 500000] {
+        let tsp = Points::along_strip(size);
+        let mut rng = StdRng::seed_from_u64(size as u64);
+        let sizes: Vec<u64> = (0..size).map(|_| rng.gen_range(100..100000)).collect();
+        group.bench_function(BenchmarkId::new("64M", size), |b| {
+            b.iter(|| optimize_window(&tsp, &sizes, 64 << 20, (0..size).collect(), false, false))
+        });
+    }
+}
+
+impl Points {
+    // Points scattered along a thin strip, in order, with neighbors picked from the points close
+    // by in that order, so that it's cheap to build at sparse-tsp sizes
+    fn along_strip(count: usize) -> Points {
+        let mut rng = StdRng::seed_from_u64(45);
+        let mut instance = Points {
+            neighbors: Vec::new(),
+            points: (0..count)
+                .map(|i| {
+                    (
+                        i as f64 * 10.0 + rng.gen_range(0.0..50.0),
+                        rng.gen_range(0.0..50.0),
+                    )
+                })
+                .collect(),
+        };
+        instance.neighbors = (0..count)
+            .map(|i| {
+                let nearby = i.saturating_sub(NEIGHBORS)..(i + NEIGHBORS + 1).min(count);
+                let mut others: Vec<usize> = nearby.filter(|&j| j != i).collect();
+                others.sort_by_key(|&j| instance.retrieve_distance(i, j));
+                others.truncate(NEIGHBORS);
+                others
+            })
+            .collect();
+        instance
+    }
+
+    fn distance((x1, y1): (f64, f64), (x2, y2): (f64, f64)) -> u32 {
+        ((x1 - x2).powi(2) + (y1 - y2).powi(2)).sqrt().round() as u32
+    }
+}
+
+criterion_group!(benches, bench_flips, bench_twoopt, bench_window);
+criterion_main!(benches);
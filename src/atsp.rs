@@ -0,0 +1,256 @@
+use num_traits::int::PrimInt;
+
+use crate::oropt::{or_opt_path, OrOptInstance};
+use crate::tour::positions;
+use crate::tsp::NEIGHBOR_SIZE;
+
+/// A tsp instance where the cost of an edge depends on its direction. For file ordering, the cost
+/// of placing `to` right after `from` is lower when `from` already contains most of `to`, but the
+/// reverse need not hold. Distances are stored row-major, with rows being the `from` node.
+#[derive(Clone)]
+pub struct AsymmetricTsp<T: PrimInt> {
+    pub distances: Vec<T>,
+    pub count: usize,
+    // The nodes that are cheapest to visit after each node
+    pub successors: Vec<Vec<usize>>,
+    // The nodes that are cheapest to visit before each node
+    pub predecessors: Vec<Vec<usize>>,
+}
+
+impl<T: PrimInt> AsymmetricTsp<T> {
+    pub fn new(distances: Vec<T>, count: usize) -> AsymmetricTsp<T> {
+        assert!(distances.len() == count * count);
+        let successors = (0..count)
+            .map(|i| nearest(count, i, |j| distances[i * count + j]))
+            .collect();
+        let predecessors = (0..count)
+            .map(|i| nearest(count, i, |j| distances[j * count + i]))
+            .collect();
+        AsymmetricTsp {
+            distances,
+            count,
+            successors,
+            predecessors,
+        }
+    }
+
+    pub fn retrieve_distance(&self, from: usize, to: usize) -> u64 {
+        self.distances[from * self.count + to].to_u64().unwrap()
+    }
+
+    // Length of the open path through indices, following their order
+    pub fn calculate_distance(&self, indices: &[usize]) -> u64 {
+        let mut distance: u64 = 0;
+        for pair in indices.windows(2) {
+            distance += self.retrieve_distance(pair[0], pair[1]);
+        }
+        distance
+    }
+
+    pub fn generate_nearest_neighbor_path(&self, index: usize) -> Vec<usize> {
+        let mut used = vec![false; self.count];
+        let mut path = vec![index];
+        used[index] = true;
+        let mut last_index = index;
+        while path.len() < self.count {
+            let next_index = match self.successors[last_index].iter().find(|&&j| !used[j]) {
+                Some(&j) => j,
+                None => (0..self.count)
+                    .filter(|&j| !used[j])
+                    .min_by_key(|&j| self.retrieve_distance(last_index, j))
+                    .unwrap(),
+            };
+            path.push(next_index);
+            used[next_index] = true;
+            last_index = next_index;
+        }
+        path
+    }
+
+    // Distance between two positions on a path, where None stands for the open end of the path
+    fn edge(&self, from: Option<usize>, to: Option<usize>) -> i64 {
+        match (from, to) {
+            (Some(from), Some(to)) => self.retrieve_distance(from, to) as i64,
+            _ => 0,
+        }
+    }
+}
+
+fn nearest<T: PrimInt, F: Fn(usize) -> T>(count: usize, index: usize, distance: F) -> Vec<usize> {
+    let mut candidates: Vec<usize> = (0..count).filter(|&j| j != index).collect();
+    candidates.sort_by_key(|&j| (distance(j), j));
+    candidates.truncate(NEIGHBOR_SIZE);
+    candidates
+}
+
+impl<T: PrimInt> OrOptInstance for AsymmetricTsp<T> {
+    fn edge_cost(&self, from: usize, to: usize) -> u64 {
+        self.retrieve_distance(from, to)
+    }
+
+    fn predecessors(&self, node: usize) -> &[usize] {
+        &self.predecessors[node]
+    }
+
+    fn successors(&self, node: usize) -> &[usize] {
+        &self.successors[node]
+    }
+}
+
+/// Improves a path over an asymmetric instance. Every move preserves the direction of the path
+/// segments it touches, since reversing a segment changes the cost of every edge inside it. The
+/// pinned ends of the path never move.
+pub fn optimize_atsp<T: PrimInt>(
+    tsp: &AsymmetricTsp<T>,
+    mut path: Vec<usize>,
+    pin_start: bool,
+    pin_end: bool,
+) -> Vec<usize> {
+    let original_distance = tsp.calculate_distance(&path);
+    if path.len() < 3 {
+        return path;
+    }
+    loop {
+        let improved = or_opt_path(tsp, &mut path, pin_start, pin_end, false)
+            || segment_exchange(tsp, &mut path, pin_start, pin_end);
+        if !improved {
+            log::debug!(
+                "StartingDistance={}, EndingDistance={}",
+                original_distance,
+                tsp.calculate_distance(&path)
+            );
+            return path;
+        }
+    }
+}
+
+// The 3-opt move that keeps every segment's orientation: for consecutive segments
+// a->[b..c]->[d..e]->f, swap the two bracketed segments to get a->[d..e]->[b..c]->f. When the
+// start of the path isn't pinned, a may be the open start of the path.
+fn segment_exchange<T: PrimInt>(
+    tsp: &AsymmetricTsp<T>,
+    path: &mut [usize],
+    pin_start: bool,
+    pin_end: bool,
+) -> bool {
+    let count = path.len();
+    let positions = positions(path);
+    let first_b = if pin_start { 1 } else { 0 };
+    for b_position in first_b..count - 1 {
+        let a = if b_position > 0 {
+            Some(path[b_position - 1])
+        } else {
+            None
+        };
+        let b = path[b_position];
+        let ab = tsp.edge(a, Some(b));
+        let d_positions: Vec<usize> = match a {
+            Some(a) => tsp.successors[a].iter().map(|&d| positions[d]).collect(),
+            None => (1..count).collect(),
+        };
+        for j in d_positions {
+            if j <= b_position {
+                continue;
+            }
+            let c = path[j - 1];
+            let d = path[j];
+            let cd = tsp.edge(Some(c), Some(d));
+            let ad = tsp.edge(a, Some(d));
+            for &e in &tsp.predecessors[b] {
+                let k = positions[e];
+                if k < j || (pin_end && k == count - 1) {
+                    continue;
+                }
+                let f = path.get(k + 1).copied();
+                let old_distance = ab + cd + tsp.edge(Some(e), f);
+                let new_distance = ad + tsp.edge(Some(e), Some(b)) + tsp.edge(Some(c), f);
+                if new_distance < old_distance {
+                    path[b_position..=k].rotate_left(j - b_position);
+                    return true;
+                }
+            }
+        }
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::atsp::*;
+    use crate::testutils::*;
+
+    // Going forward from i to i + 1 is cheap, anything else is expensive
+    fn build_directed_chain(count: usize) -> AsymmetricTsp<u8> {
+        let mut distances = vec![0; count * count];
+        for i in 0..count {
+            for j in 0..count {
+                distances[i * count + j] = if j == i + 1 {
+                    1
+                } else if i == j {
+                    0
+                } else {
+                    100
+                };
+            }
+        }
+        AsymmetricTsp::new(distances, count)
+    }
+
+    #[test]
+    fn retrieve_distance_depends_on_direction() {
+        let tsp = build_directed_chain(5);
+        assert_eq!(1, tsp.retrieve_distance(2, 3));
+        assert_eq!(100, tsp.retrieve_distance(3, 2));
+        assert_eq!(4, tsp.calculate_distance(&[0, 1, 2, 3, 4]));
+        assert_eq!(400, tsp.calculate_distance(&[4, 3, 2, 1, 0]));
+    }
+
+    #[test]
+    fn nearest_neighbor_path_follows_successors() {
+        let tsp = build_directed_chain(8);
+        let path = tsp.generate_nearest_neighbor_path(0);
+        assert_eq!(path, (0..8).collect::<Vec<usize>>());
+        let path = tsp.generate_nearest_neighbor_path(5);
+        check_permutation(&path, 7);
+    }
+
+    #[test]
+    fn optimize_atsp_orders_reversed_chain() {
+        let tsp = build_directed_chain(20);
+        let path = optimize_atsp(&tsp, (0..20).rev().collect(), false, false);
+        check_permutation(&path, 19);
+        assert_eq!(19, tsp.calculate_distance(&path));
+    }
+
+    #[test]
+    fn optimize_atsp_respects_pinned_ends() {
+        let tsp = build_directed_chain(12);
+        let mut path: Vec<usize> = (0..12).rev().collect();
+        path.swap(0, 4);
+        let start = path[0];
+        let end = path[11];
+        let optimized = optimize_atsp(&tsp, path.clone(), true, true);
+        check_permutation(&optimized, 11);
+        assert_eq!(start, optimized[0]);
+        assert_eq!(end, optimized[11]);
+        assert!(tsp.calculate_distance(&optimized) < tsp.calculate_distance(&path));
+    }
+
+    #[test]
+    fn segment_exchange_can_move_the_start() {
+        let tsp = build_directed_chain(6);
+        let mut path = vec![3, 4, 5, 0, 1, 2];
+        assert!(segment_exchange(&tsp, &mut path, false, false));
+        assert_eq!(path, vec![0, 1, 2, 3, 4, 5]);
+        let mut path = vec![3, 4, 5, 0, 1, 2];
+        assert!(!segment_exchange(&tsp, &mut path, true, false));
+    }
+
+    #[test]
+    fn segment_exchange_swaps_segments() {
+        let tsp = build_directed_chain(6);
+        let mut path = vec![0, 3, 4, 1, 2, 5];
+        assert!(segment_exchange(&tsp, &mut path, true, false));
+        assert_eq!(path, vec![0, 1, 2, 3, 4, 5]);
+    }
+}
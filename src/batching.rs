@@ -0,0 +1,192 @@
+use crate::minhash::{similarity_to_distance, Minhash};
+
+// Rounds of assignment and medoid updates. Most of the improvement comes in the first couple.
+const ITERATIONS: usize = 3;
+
+// Medoid updates compare this many candidate medoids against this many members of the batch,
+// rather than every pair of members
+const MEDOID_CANDIDATES: usize = 32;
+const MEDOID_SAMPLE: usize = 256;
+
+/**
+ * Splits files into batches of similar files, none larger than max_batch, using
+ * capacity-constrained k-medoids over the minhash signatures.
+ *
+ * We use as few batches as possible, and every batch gets at most ceil(n / k) files, so batches
+ * are balanced. Files are assigned in order of how much they'd lose by not getting their nearest
+ * medoid, so the files with a clear home get it before the batches fill up.
+ *
+ * Returns the indices of the signatures in each batch.
+ */
+pub fn similarity_batches(signatures: &[Minhash], max_batch: usize) -> Vec<Vec<usize>> {
+    let count = signatures.len();
+    assert!(max_batch > 0);
+    if count <= max_batch {
+        return vec![(0..count).collect()];
+    }
+    let batch_count = count.div_ceil(max_batch);
+    let capacity = count.div_ceil(batch_count);
+
+    let mut medoids = farthest_first(signatures, batch_count);
+    let mut batches = Vec::new();
+    for iteration in 0..ITERATIONS {
+        batches = assign(signatures, &medoids, capacity);
+        let updated: Vec<usize> = batches
+            .iter()
+            .map(|batch| choose_medoid(signatures, batch))
+            .collect();
+        log::debug!("Batching iteration {}, medoids={:?}", iteration, updated);
+        if updated == medoids {
+            break;
+        }
+        medoids = updated;
+    }
+    batches
+}
+
+fn distance(m1: &Minhash, m2: &Minhash) -> u64 {
+    similarity_to_distance(m1.score(m2)) as u64
+}
+
+// Deterministic seeding: start from the first file, then repeatedly take the file farthest from
+// every medoid chosen so far
+fn farthest_first(signatures: &[Minhash], batch_count: usize) -> Vec<usize> {
+    let mut medoids = vec![0];
+    let mut nearest: Vec<u64> = signatures
+        .iter()
+        .map(|s| distance(s, &signatures[0]))
+        .collect();
+    while medoids.len() < batch_count {
+        let next = (0..signatures.len())
+            .filter(|i| !medoids.contains(i))
+            .max_by_key(|&i| (nearest[i], std::cmp::Reverse(i)))
+            .unwrap();
+        medoids.push(next);
+        for (i, s) in signatures.iter().enumerate() {
+            nearest[i] = nearest[i].min(distance(s, &signatures[next]));
+        }
+    }
+    medoids
+}
+
+fn assign(signatures: &[Minhash], medoids: &[usize], capacity: usize) -> Vec<Vec<usize>> {
+    let distances: Vec<Vec<u64>> = signatures
+        .iter()
+        .map(|s| {
+            medoids
+                .iter()
+                .map(|&m| distance(s, &signatures[m]))
+                .collect()
+        })
+        .collect();
+    // How much worse a file's second choice is than its first
+    let regret = |i: usize| {
+        let mut sorted = distances[i].clone();
+        sorted.sort_unstable();
+        sorted.get(1).map_or(0, |second| second - sorted[0])
+    };
+    let mut order: Vec<usize> = (0..signatures.len()).collect();
+    order.sort_by_cached_key(|&i| (std::cmp::Reverse(regret(i)), i));
+
+    let mut batches: Vec<Vec<usize>> = vec![Vec::new(); medoids.len()];
+    for i in order {
+        let batch = (0..medoids.len())
+            .filter(|&b| batches[b].len() < capacity)
+            .min_by_key(|&b| (distances[i][b], b))
+            .unwrap();
+        batches[batch].push(i);
+    }
+    for batch in batches.iter_mut() {
+        batch.sort_unstable();
+    }
+    batches
+}
+
+// The member with the smallest total distance to a sample of the batch
+fn choose_medoid(signatures: &[Minhash], batch: &[usize]) -> usize {
+    let sample = spread(batch, MEDOID_SAMPLE);
+    spread(batch, MEDOID_CANDIDATES)
+        .into_iter()
+        .min_by_key(|&c| {
+            let total: u64 = sample
+                .iter()
+                .map(|&m| distance(&signatures[c], &signatures[m]))
+                .sum();
+            (total, c)
+        })
+        .unwrap()
+}
+
+// Up to n members, evenly spaced through the batch
+fn spread(batch: &[usize], n: usize) -> Vec<usize> {
+    if batch.len() <= n {
+        return batch.to_vec();
+    }
+    (0..n).map(|k| batch[k * batch.len() / n]).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::batching::*;
+    use crate::minhash::*;
+
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+    use std::fs::File;
+    use std::io::Write;
+    use tempfile::tempdir;
+
+    // Files from different groups are interleaved, the way a directory walk might return them
+    fn build_interleaved_signatures(groups: usize, per_group: usize) -> Vec<Minhash> {
+        let mut rng = StdRng::seed_from_u64(31);
+        let temp_dir = tempdir().unwrap();
+        let bases: Vec<Vec<u8>> = (0..groups)
+            .map(|_| (0..2048).map(|_| rng.gen()).collect())
+            .collect();
+        let mut signatures = Vec::new();
+        for member in 0..per_group {
+            for (group, base) in bases.iter().enumerate() {
+                let mut bytes = base.clone();
+                let position = rng.gen_range(0..bytes.len());
+                bytes[position] = rng.gen();
+                let path = temp_dir.path().join(format!("{}_{}", group, member));
+                File::create(&path).unwrap().write_all(&bytes).unwrap();
+                signatures.push(minhash_stream(&MinhashTarget::File(path)).unwrap());
+            }
+        }
+        signatures
+    }
+
+    #[test]
+    fn small_inputs_are_one_batch() {
+        let signatures = build_interleaved_signatures(2, 3);
+        assert_eq!(
+            vec![(0..6).collect::<Vec<usize>>()],
+            similarity_batches(&signatures, 6)
+        );
+    }
+
+    #[test]
+    fn batches_group_similar_files() {
+        let signatures = build_interleaved_signatures(4, 5);
+        let mut batches = similarity_batches(&signatures, 5);
+        assert_eq!(4, batches.len());
+        batches.sort();
+        for batch in batches {
+            assert_eq!(5, batch.len());
+            // Files of one group are 4 apart, since groups were interleaved
+            assert!(batch.iter().all(|&i| i % 4 == batch[0] % 4));
+        }
+    }
+
+    #[test]
+    fn batches_are_balanced() {
+        let signatures = build_interleaved_signatures(3, 7);
+        let batches = similarity_batches(&signatures, 10);
+        assert_eq!(3, batches.len());
+        let mut seen: Vec<usize> = batches.iter().flatten().copied().collect();
+        seen.sort();
+        assert_eq!((0..21).collect::<Vec<usize>>(), seen);
+        assert!(batches.iter().all(|b| b.len() <= 7));
+    }
+}
@@ -1,4 +1,9 @@
+use clap::builder::RangedU64ValueParser;
 use clap::Subcommand;
+use simsort::compress::*;
+use simsort::hierarchical::*;
+use simsort::lsh::*;
+use simsort::lz77::*;
 use simsort::minhash::*;
 use simsort::tsp::*;
 use simsort::*;
@@ -7,6 +12,7 @@ use std::fs::File;
 use std::io::Error;
 use std::io::{BufRead, BufReader};
 use std::path::{Path, PathBuf};
+use std::time::Duration;
 
 use clap::Parser;
 
@@ -38,6 +44,63 @@ fn main() {
                     }
                 }
             }
+            Command::Similar {
+                filelist,
+                targetfile,
+                count,
+                bands,
+                rows,
+            } => match find_similar_files(&filelist, &targetfile, count, bands, rows) {
+                Ok(similar) => {
+                    for pair in similar {
+                        println!("{:?} {:?}", pair.1, pair.0.get_path());
+                    }
+                }
+                Err(err) => {
+                    eprintln!("Error: {}", err);
+                    std::process::exit(1);
+                }
+            },
+            Command::Clusters { filelist, count } => {
+                for (cluster, path) in cluster_files(&filelist, count) {
+                    println!("{} {:?}", cluster, path);
+                }
+            }
+            Command::Compress {
+                orderings,
+                directory,
+                compressors,
+            } => match compare_compression(&orderings, directory.as_deref(), &compressors) {
+                Ok(rows) => {
+                    let mut header = vec!["ordering".to_string(), "tar_bytes".to_string()];
+                    for setting in &compressors {
+                        header.push(format!("{}_bytes", setting));
+                        header.push(format!("{}_seconds", setting));
+                    }
+                    println!("{}", header.join("\t"));
+                    for (ordering, tar_size, results) in rows {
+                        let mut row = vec![ordering, tar_size.to_string()];
+                        for (size, time) in results {
+                            row.push(size.to_string());
+                            row.push(format!("{:.3}", time.as_secs_f64()));
+                        }
+                        println!("{}", row.join("\t"));
+                    }
+                }
+                Err(err) => {
+                    eprintln!("Error: {}", err);
+                    std::process::exit(1);
+                }
+            },
+            Command::Estimate { filelist, window } => {
+                match estimate_compressed_size(&read_paths(&filelist), window) {
+                    Ok(size) => println!("estimated compressed size is {} bytes", size),
+                    Err(err) => {
+                        eprintln!("Error: {}", err);
+                        std::process::exit(1);
+                    }
+                }
+            }
         },
         Err(err) => {
             eprintln!("Error: {}", err);
@@ -67,11 +130,18 @@ fn read_files(filepath: &Path) -> Vec<MinhashTarget> {
         .collect()
 }
 
+fn read_paths(filepath: &Path) -> Vec<PathBuf> {
+    read_files(filepath)
+        .into_iter()
+        .map(|target| target.get_path().to_path_buf())
+        .collect()
+}
+
 //  Takes the output of tar --list, and calculates the path distance for that archive
 fn calculate_path_distance_from_file(filepath: &Path) -> u64 {
     let targets = read_files(filepath);
-    let (distances, hashed_files, _) = compute_distances(targets);
-    let tsp = Tsp::new(distances, hashed_files.len());
+    let (distances, hashed_files, _) = compute_distances(targets, MinhashSettings::default(), 0.0);
+    let tsp = Tsp::from_distances(distances);
     let indices: Vec<usize> = (0..hashed_files.len()).collect();
     tsp.calculate_distance(&indices)
 }
@@ -89,6 +159,100 @@ fn calculate_file_distances(filepath: &Path, targetfile: &Path) -> Result<Vec<(M
     Ok(distances)
 }
 
+// Given the output of tar --list in a file, finds the files most similar to targetfile, using an
+// LSH index so that only likely matches are scored
+fn find_similar_files(
+    filepath: &Path,
+    targetfile: &Path,
+    count: usize,
+    bands: usize,
+    rows: usize,
+) -> Result<Vec<(MinhashTarget, f64)>, Error> {
+    let file_minhash = minhash_stream(&MinhashTarget::File(targetfile.to_path_buf()))?;
+    let targets: Vec<MinhashTarget> = read_files(filepath);
+    let mut signatures = Vec::new();
+    for target in &targets {
+        signatures.push(minhash_stream(target)?);
+    }
+    let index = LshIndex::build(&signatures, bands, rows);
+    let candidates = index.candidates(&file_minhash);
+    Ok(
+        nearest_candidates(&file_minhash, candidates, &signatures, count)
+            .into_iter()
+            .map(|i| (targets[i].clone(), file_minhash.score(&signatures[i])))
+            .collect(),
+    )
+}
+
+// Given the output of tar --list in a file, cuts the average-linkage dendrogram of the files into
+// count clusters. Files are listed in optimal leaf order, with the cluster each belongs to.
+fn cluster_files(filepath: &Path, count: usize) -> Vec<(usize, PathBuf)> {
+    let targets = read_files(filepath);
+    let (distances, hashed_files, _) = compute_distances(targets, MinhashSettings::default(), 0.0);
+    let dendrogram = Dendrogram::average_linkage(&distances);
+    let clusters = dendrogram.clusters(count);
+    dendrogram
+        .optimal_leaf_order(&distances)
+        .into_iter()
+        .map(|i| (clusters[i], hashed_files[i].clone()))
+        .collect()
+}
+
+// A file list, one path per line, or else an algorithm and simsort options to run on directory
+fn resolve_ordering(ordering: &str, directory: Option<&Path>) -> Result<Vec<PathBuf>, String> {
+    let listed = Path::new(ordering);
+    if listed.is_file() {
+        let file = File::open(listed).map_err(|e| format!("Could not open {}: {}", ordering, e))?;
+        return BufReader::new(file)
+            .lines()
+            .map(|line| line.map(PathBuf::from))
+            .collect::<Result<_, _>>()
+            .map_err(|e| format!("Could not read {}: {}", ordering, e));
+    }
+    let directory = directory.ok_or_else(|| {
+        format!(
+            "{} is not a file list, and there's no --directory to run it on",
+            ordering
+        )
+    })?;
+    let mut words = vec!["simsort".into(), directory.as_os_str().to_owned()];
+    words.extend(ordering.split_whitespace().map(|word| word.into()));
+    let args = Args::try_parse_from(words).map_err(|e| e.to_string())?;
+    order(&args)
+}
+
+// An ordering's name, the size of its uncompressed tar, and the compressed size and time for each
+// compressor
+type CompressionRow = (String, u64, Vec<(u64, Duration)>);
+
+// Tars each ordering and compresses the archive with each compressor in turn
+fn compare_compression(
+    orderings: &[String],
+    directory: Option<&Path>,
+    compressors: &[CompressorSetting],
+) -> Result<Vec<CompressionRow>, String> {
+    let mut rows = Vec::new();
+    for ordering in orderings {
+        let paths = resolve_ordering(ordering, directory)?;
+        let archive = tempfile::NamedTempFile::new()
+            .map_err(|e| format!("Could not create a temporary archive: {}", e))?;
+        write_tar(&paths, archive.path())?;
+        let tar_size = archive
+            .as_file()
+            .metadata()
+            .map_err(|e| format!("Could not read the archive size: {}", e))?
+            .len();
+        let mut results = Vec::new();
+        for setting in compressors {
+            let input = File::open(archive.path())
+                .map_err(|e| format!("Could not open the archive: {}", e))?;
+            results.push(setting.measure(input)?);
+        }
+        rows.push((ordering.clone(), tar_size, results));
+    }
+    Ok(rows)
+}
+
 #[derive(Parser, Debug)]
 pub struct AnalyzeArgs {
     #[command(subcommand)]
@@ -104,4 +268,47 @@ enum Command {
         filelist: PathBuf,
         targetfile: PathBuf,
     },
+    Similar {
+        filelist: PathBuf,
+        targetfile: PathBuf,
+        #[arg(long, default_value_t = 10)]
+        count: usize,
+        #[arg(
+            long,
+            default_value_t = DEFAULT_BANDS,
+            value_parser = RangedU64ValueParser::<usize>::new().range(1..)
+        )]
+        bands: usize,
+        #[arg(
+            long,
+            default_value_t = DEFAULT_ROWS,
+            value_parser = RangedU64ValueParser::<usize>::new().range(1..)
+        )]
+        rows: usize,
+    },
+    Clusters {
+        filelist: PathBuf,
+        #[arg(long, default_value_t = 8)]
+        count: usize,
+    },
+    /// Tars each ordering and compresses it with each compressor, printing a tab separated table
+    /// of the exact compressed sizes and how long each compressor took
+    Compress {
+        /// File lists, one path per line, or algorithms to run on --directory, with any simsort
+        /// options, like "tsp --construction best"
+        #[arg(required = true)]
+        orderings: Vec<String>,
+        #[arg(long)]
+        directory: Option<PathBuf>,
+        /// A compressor and level like xz:9, from gzip, bzip2, xz and zstd. Can be repeated.
+        #[arg(long = "compressor", default_values = ["gzip:6", "xz:6", "zstd:3"])]
+        compressors: Vec<CompressorSetting>,
+    },
+    /// Estimates the compressed size of the files in the list, concatenated in list order, for
+    /// a compressor with the given window (32K for gzip, up to 64M for xz or zstd --long)
+    Estimate {
+        filelist: PathBuf,
+        #[arg(long, default_value = "32K", value_parser = parse_size)]
+        window: usize,
+    },
 }
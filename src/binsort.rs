@@ -1,6 +1,6 @@
 use rand::Rng;
 
-use crate::tsp::Tsp;
+use crate::tsp::{Tsp, TspInstance};
 
 const BINSORT_DEFAULT_QUALITY: u8 = 15;
 
@@ -0,0 +1,106 @@
+use std::ops::Range;
+
+// How far, as a fraction of the ideal block size, a block boundary may move from where it would
+// make every block the same size, to land between less similar files
+pub const BLOCK_SLACK: f64 = 0.1;
+
+/**
+ * Cuts an ordering into `count` runs of consecutive files with about the same number of bytes,
+ * for compressors that compress blocks independently: `xz -T`, `zstd -T` without long mode, or
+ * 7z solid blocks. Similarity between files in different blocks is wasted, so each cut goes
+ * between the least similar pair of neighboring files within BLOCK_SLACK of its ideal position.
+ * The ordering already keeps similar files together, so this keeps them in the same block.
+ *
+ * `sizes` holds the size of each file, and `distance_at(p)` how dissimilar the files on either
+ * side of a cut just before position p are. Each cut has its own range of positions, measured
+ * from the start of the ordering so that small errors don't add up, and none of them overlap, so
+ * picking the best cut in each range independently gives the best set of cuts.
+ *
+ * Returns the range of positions in each block, in order. With fewer files than blocks, or files
+ * much larger than a block, some cuts have nowhere to go and there are fewer blocks.
+ */
+pub fn partition_blocks<F: FnMut(usize) -> u64>(
+    sizes: &[u64],
+    count: usize,
+    mut distance_at: F,
+) -> Vec<Range<usize>> {
+    let file_count = sizes.len();
+    // offsets[p] is the number of bytes before position p
+    let mut offsets = Vec::with_capacity(file_count + 1);
+    let mut total = 0;
+    offsets.push(0);
+    for &size in sizes {
+        total += size;
+        offsets.push(total);
+    }
+    let slack = (total as f64 / count.max(1) as f64 * BLOCK_SLACK) as u64;
+    let mut starts = vec![0];
+    for block in 1..count {
+        let target = (total as u128 * block as u128 / count as u128) as u64;
+        // Cuts go strictly after the last one, and never after the last file
+        let first = starts.last().unwrap() + 1;
+        if first >= file_count {
+            break;
+        }
+        let candidates = &offsets[first..file_count];
+        let lo =
+            first + candidates.partition_point(|&offset| offset < target.saturating_sub(slack));
+        let hi = first + candidates.partition_point(|&offset| offset <= target + slack);
+        let off_target = |p: usize| offsets[p].abs_diff(target);
+        let cut = if lo < hi {
+            (lo..hi)
+                .max_by_key(|&p| (distance_at(p), std::cmp::Reverse(off_target(p))))
+                .unwrap()
+        } else {
+            // Nothing within the slack, so a large file straddles the target. Cut next to it.
+            let after = first + candidates.partition_point(|&offset| offset < target);
+            [after.saturating_sub(1), after]
+                .into_iter()
+                .filter(|p| (first..file_count).contains(p))
+                .min_by_key(|&p| off_target(p))
+                .unwrap()
+        };
+        if cut > *starts.last().unwrap() {
+            starts.push(cut);
+        }
+    }
+    starts.push(file_count);
+    starts
+        .windows(2)
+        .map(|pair| pair[0]..pair[1])
+        .filter(|range| !range.is_empty())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::blocks::*;
+
+    #[test]
+    fn equal_files_split_evenly() {
+        let blocks = partition_blocks(&[100; 12], 3, |_| 0);
+        assert_eq!(vec![0..4, 4..8, 8..12], blocks);
+    }
+
+    #[test]
+    fn cuts_move_to_dissimilar_neighbors() {
+        // The files on either side of position 11 are unrelated, and it's within the slack of
+        // the ideal cut at 10
+        let sizes = [50; 20];
+        let blocks = partition_blocks(&sizes, 2, |p| if p == 11 { 255 } else { 10 });
+        assert_eq!(vec![0..11, 11..20], blocks);
+        // Position 14 is too far from the middle to be worth the imbalance
+        let blocks = partition_blocks(&sizes, 2, |p| if p == 14 { 255 } else { 10 });
+        assert_eq!(vec![0..10, 10..20], blocks);
+    }
+
+    #[test]
+    fn large_files_and_few_files_give_fewer_blocks() {
+        let blocks = partition_blocks(&[10, 1000, 10], 3, |_| 0);
+        assert_eq!(3, blocks.len());
+        assert_eq!(0..3, blocks[0].start..blocks[blocks.len() - 1].end);
+        assert_eq!(vec![0..1, 1..2], partition_blocks(&[5, 5], 4, |_| 0));
+        assert!(partition_blocks(&[], 4, |_| 0).is_empty());
+        assert_eq!(vec![0..3], partition_blocks(&[1, 2, 3], 1, |_| 0));
+    }
+}
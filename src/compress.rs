@@ -0,0 +1,376 @@
+use std::fmt;
+use std::fs::File;
+use std::io::{copy, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::str::FromStr;
+use std::thread;
+use std::time::{Duration, Instant};
+
+const TAR_BLOCK: u64 = 512;
+// Longest name that fits in a tar header
+const TAR_NAME_LENGTH: usize = 100;
+
+/// A compressor installed on the system, that we can run to measure real compressed sizes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compressor {
+    Gzip,
+    Bzip2,
+    Xz,
+    Zstd,
+}
+
+impl Compressor {
+    fn program(&self) -> &'static str {
+        match self {
+            Self::Gzip => "gzip",
+            Self::Bzip2 => "bzip2",
+            Self::Xz => "xz",
+            Self::Zstd => "zstd",
+        }
+    }
+
+    fn levels(&self) -> std::ops::RangeInclusive<u32> {
+        match self {
+            Self::Gzip | Self::Bzip2 | Self::Xz => 1..=9,
+            Self::Zstd => 1..=22,
+        }
+    }
+
+    fn default_level(&self) -> u32 {
+        match self {
+            Self::Gzip => 6,
+            Self::Bzip2 => 9,
+            Self::Xz => 6,
+            Self::Zstd => 3,
+        }
+    }
+}
+
+/// A compressor and the level to run it at, written like "xz:9", or "xz" for its default level.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CompressorSetting {
+    pub compressor: Compressor,
+    pub level: u32,
+}
+
+impl CompressorSetting {
+    fn command(&self) -> Command {
+        let mut command = Command::new(self.compressor.program());
+        command.arg("-c").arg(format!("-{}", self.level));
+        match self.compressor {
+            // Multithreaded xz splits the input into blocks, which changes the output size
+            Compressor::Xz => {
+                command.arg("-T1");
+            }
+            Compressor::Zstd => {
+                command.arg("-q");
+                if self.level > 19 {
+                    command.arg("--ultra");
+                }
+            }
+            _ => {}
+        }
+        command
+    }
+
+    /**
+     * Compresses whatever `input` produces, returning the compressed size in bytes and how long
+     * the compressor took. The compressed output is counted and thrown away.
+     */
+    pub fn measure<R: Read + Send>(&self, mut input: R) -> Result<(u64, Duration), String> {
+        let program = self.compressor.program();
+        let start = Instant::now();
+        let mut child = self
+            .command()
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .map_err(|e| format!("Could not run {}: {}", program, e))?;
+        let mut stdin = child.stdin.take().unwrap();
+        let mut stdout = child.stdout.take().unwrap();
+        // Feed the compressor from another thread, or it could block on a full output pipe while
+        // we block on a full input pipe
+        let size = thread::scope(|scope| {
+            let writer = scope.spawn(move || -> std::io::Result<u64> {
+                let written = copy(&mut input, &mut stdin)?;
+                stdin.flush()?;
+                Ok(written)
+            });
+            let size = copy(&mut stdout, &mut std::io::sink());
+            let written = writer.join().unwrap();
+            written.and(size)
+        })
+        .map_err(|e| format!("Could not compress with {}: {}", program, e))?;
+        let status = child
+            .wait()
+            .map_err(|e| format!("Could not wait for {}: {}", program, e))?;
+        if !status.success() {
+            return Err(format!("{} exited with {}", program, status));
+        }
+        Ok((size, start.elapsed()))
+    }
+}
+
+impl FromStr for CompressorSetting {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<CompressorSetting, String> {
+        let (name, level) = match text.split_once(':') {
+            Some((name, level)) => (name, Some(level)),
+            None => (text, None),
+        };
+        let compressor = match name {
+            "gzip" => Compressor::Gzip,
+            "bzip2" => Compressor::Bzip2,
+            "xz" => Compressor::Xz,
+            "zstd" => Compressor::Zstd,
+            _ => return Err(format!("Unknown compressor {:?}", name)),
+        };
+        let level = match level {
+            Some(level) => level
+                .parse()
+                .map_err(|e| format!("Could not parse level {:?}: {}", level, e))?,
+            None => compressor.default_level(),
+        };
+        if !compressor.levels().contains(&level) {
+            return Err(format!(
+                "{} does not have a level {}, it goes from {} to {}",
+                name,
+                level,
+                compressor.levels().start(),
+                compressor.levels().end()
+            ));
+        }
+        Ok(CompressorSetting { compressor, level })
+    }
+}
+
+impl fmt::Display for CompressorSetting {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.compressor.program(), self.level)
+    }
+}
+
+/// Reads the contents of the files one after another, as if they were one stream. Directories
+/// have no contents and are skipped.
+pub struct ConcatenatedFiles<'a> {
+    paths: &'a [PathBuf],
+    current: Option<File>,
+}
+
+impl<'a> ConcatenatedFiles<'a> {
+    pub fn new(paths: &'a [PathBuf]) -> ConcatenatedFiles<'a> {
+        ConcatenatedFiles {
+            paths,
+            current: None,
+        }
+    }
+}
+
+impl Read for ConcatenatedFiles<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        loop {
+            if let Some(file) = &mut self.current {
+                let read = file.read(buf)?;
+                if read > 0 || buf.is_empty() {
+                    return Ok(read);
+                }
+                self.current = None;
+            }
+            match self.paths.split_first() {
+                Some((path, rest)) => {
+                    self.paths = rest;
+                    if !path.is_dir() {
+                        self.current = Some(File::open(path)?);
+                    }
+                }
+                None => return Ok(0),
+            }
+        }
+    }
+}
+
+/**
+ * Writes a tar archive of the given paths, in order, to `destination`, using the system tar the
+ * same way the readme suggests piping simsort into it. Directories are archived without their
+ * contents, since their contents are listed separately.
+ */
+pub fn write_tar(paths: &[PathBuf], destination: &Path) -> Result<(), String> {
+    let output =
+        File::create(destination).map_err(|e| format!("Could not create archive: {}", e))?;
+    let mut child = Command::new("tar")
+        .args(["--no-recursion", "--null", "-T", "-", "-cf", "-"])
+        .stdin(Stdio::piped())
+        .stdout(output)
+        .spawn()
+        .map_err(|e| format!("Could not run tar: {}", e))?;
+    let mut list = Vec::new();
+    for path in paths {
+        list.extend_from_slice(path.as_os_str().as_encoded_bytes());
+        list.push(0);
+    }
+    let mut stdin = child.stdin.take().unwrap();
+    stdin
+        .write_all(&list)
+        .map_err(|e| format!("Could not send the file list to tar: {}", e))?;
+    drop(stdin);
+    let status = child
+        .wait()
+        .map_err(|e| format!("Could not wait for tar: {}", e))?;
+    if !status.success() {
+        return Err(format!("tar exited with {}", status));
+    }
+    Ok(())
+}
+
+/**
+ * Bytes a path takes up in a GNU tar archive, archived under `name`: a 512 byte header, then its
+ * contents padded to a multiple of 512 bytes. A name or link target longer than the 100 bytes the
+ * header has room for goes in a ././@LongLink entry in front, which takes another header and the
+ * name itself, padded the same way. tar drops leading slashes from names, and adds a trailing
+ * slash to directories.
+ */
+pub fn tar_entry_size(path: &Path, name: &Path) -> u64 {
+    let metadata = path.symlink_metadata().ok();
+    let name = name.as_os_str().as_encoded_bytes();
+    let name = &name[name.iter().take_while(|&&b| b == b'/').count()..];
+    let mut name_length = name.len();
+    if metadata.as_ref().is_some_and(|m| m.is_dir()) && !name.ends_with(b"/") {
+        name_length += 1;
+    }
+    let mut size = TAR_BLOCK + long_name_size(name_length);
+    match metadata {
+        Some(metadata) if metadata.is_file() => {
+            size += metadata.len().div_ceil(TAR_BLOCK) * TAR_BLOCK;
+        }
+        Some(metadata) if metadata.is_symlink() => {
+            if let Ok(target) = path.read_link() {
+                size += long_name_size(target.as_os_str().len());
+            }
+        }
+        _ => {}
+    }
+    size
+}
+
+// Bytes of the ././@LongLink entry for a name of this length, if it needs one. The name is
+// stored with a terminating NUL.
+fn long_name_size(length: usize) -> u64 {
+    if length <= TAR_NAME_LENGTH {
+        0
+    } else {
+        TAR_BLOCK + (length as u64 + 1).div_ceil(TAR_BLOCK) * TAR_BLOCK
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use tempfile::tempdir;
+
+    use crate::compress::*;
+
+    #[test]
+    fn settings_parse() {
+        let setting: CompressorSetting = "xz:9".parse().unwrap();
+        assert_eq!(Compressor::Xz, setting.compressor);
+        assert_eq!(9, setting.level);
+        assert_eq!(
+            "gzip:6",
+            "gzip".parse::<CompressorSetting>().unwrap().to_string()
+        );
+        assert_eq!(22, "zstd:22".parse::<CompressorSetting>().unwrap().level);
+        assert!("gzip:12".parse::<CompressorSetting>().is_err());
+        assert!("lzma".parse::<CompressorSetting>().is_err());
+    }
+
+    #[test]
+    fn gzip_measures_compressed_size() {
+        let data = b"simsort ".repeat(10000);
+        let (size, _) = "gzip:9"
+            .parse::<CompressorSetting>()
+            .unwrap()
+            .measure(&data[..])
+            .unwrap();
+        assert!(size > 0 && size < 1000, "{}", size);
+    }
+
+    #[test]
+    fn concatenated_files_skip_directories() {
+        let tempdir = tempdir().unwrap();
+        let paths = vec![
+            tempdir.path().join("a"),
+            tempdir.path().to_path_buf(),
+            tempdir.path().join("empty"),
+            tempdir.path().join("b"),
+        ];
+        fs::write(&paths[0], b"abc").unwrap();
+        fs::write(&paths[2], b"").unwrap();
+        fs::write(&paths[3], b"def").unwrap();
+        let mut contents = String::new();
+        ConcatenatedFiles::new(&paths)
+            .read_to_string(&mut contents)
+            .unwrap();
+        assert_eq!("abcdef", contents);
+    }
+
+    #[test]
+    fn tar_keeps_the_order() {
+        let tempdir = tempdir().unwrap();
+        let paths: Vec<PathBuf> = ["b", "c", "a"]
+            .iter()
+            .map(|name| tempdir.path().join(name))
+            .collect();
+        for path in &paths {
+            fs::write(path, b"abc").unwrap();
+        }
+        let archive = tempdir.path().join("archive.tar");
+        write_tar(&paths, &archive).unwrap();
+        let listing = Command::new("tar")
+            .arg("-tf")
+            .arg(&archive)
+            .output()
+            .unwrap();
+        let listed: Vec<String> = String::from_utf8(listing.stdout)
+            .unwrap()
+            .lines()
+            .map(|line| line.to_string())
+            .collect();
+        let expected: Vec<String> = paths
+            .iter()
+            .map(|path| path.to_string_lossy().trim_start_matches('/').to_string())
+            .collect();
+        assert_eq!(expected, listed);
+        let entries: u64 = paths.iter().map(|path| tar_entry_size(path, path)).sum();
+        assert_eq!(3 * 1024, entries);
+    }
+
+    #[test]
+    fn long_names_are_counted() {
+        let tempdir = tempdir().unwrap();
+        let directory = tempdir.path().join("d".repeat(110));
+        fs::create_dir(&directory).unwrap();
+        let file = directory.join("f".repeat(20));
+        fs::write(&file, b"abc").unwrap();
+        let link = tempdir.path().join("link");
+        std::os::unix::fs::symlink(file.join("t".repeat(100)), &link).unwrap();
+        let short = tempdir.path().join("short");
+        fs::write(&short, vec![0; 600]).unwrap();
+        for path in [&directory, &file, &link, &short] {
+            let archive = tempdir.path().join("entry.tar");
+            // One block per record, so the archive is the entry and the two end blocks
+            let status = Command::new("tar")
+                .args(["-b", "1", "--no-recursion", "-cf"])
+                .arg(&archive)
+                .arg(path)
+                .stderr(Stdio::null())
+                .status()
+                .unwrap();
+            assert!(status.success());
+            let expected = fs::metadata(&archive).unwrap().len() - 2 * TAR_BLOCK;
+            assert_eq!(expected, tar_entry_size(path, path), "{}", path.display());
+        }
+    }
+}
@@ -0,0 +1,236 @@
+use crate::tsp::TspInstance;
+
+/**
+ * Builds a tour with the greedy edge heuristic: take the shortest edges first, skipping any that
+ * would give a node a third edge or close a cycle early. Only edges to each node's neighbor list
+ * are considered, so this is O(n k log(n k)) rather than sorting all n² edges. Whatever fragments
+ * are left when the candidate edges run out are chained together by their nearest ends.
+ */
+pub fn greedy_edge_tour<I: TspInstance>(tsp: &I) -> Vec<usize> {
+    let mut candidates = candidate_edges(tsp)
+        .into_iter()
+        .map(|(i, j)| (tsp.retrieve_distance(i, j), i, j))
+        .collect::<Vec<(u64, usize, usize)>>();
+    candidates.sort_unstable();
+    let edges = candidates.into_iter().map(|(_, i, j)| (i, j));
+    join_fragments(tsp, &match_edges(tsp.count(), edges))
+}
+
+/**
+ * Builds a tour with the Clarke-Wright savings heuristic. Start with a star of round trips from a
+ * hub node to every other node, then repeatedly join two trips end to end where that saves the
+ * most: joining at i and j saves d(hub, i) + d(hub, j) - d(i, j). Like greedy edge, only edges
+ * to neighbors are candidates, and the hub is arbitrarily the first node.
+ */
+pub fn savings_tour<I: TspInstance>(tsp: &I) -> Vec<usize> {
+    let hub = 0;
+    let mut candidates = candidate_edges(tsp)
+        .into_iter()
+        .filter(|&(i, j)| i != hub && j != hub)
+        .map(|(i, j)| {
+            let saving = tsp.retrieve_distance(hub, i) as i64
+                + tsp.retrieve_distance(hub, j) as i64
+                - tsp.retrieve_distance(i, j) as i64;
+            (std::cmp::Reverse(saving), i, j)
+        })
+        .collect::<Vec<(std::cmp::Reverse<i64>, usize, usize)>>();
+    candidates.sort_unstable();
+    let edges = candidates.into_iter().map(|(_, i, j)| (i, j));
+    // The hub is left as a fragment of its own, and joined wherever it fits best
+    join_fragments(tsp, &match_edges(tsp.count(), edges))
+}
+
+/**
+ * Builds a tour with farthest insertion: start from a node and the node farthest from it, then
+ * repeatedly take the node farthest from the tour and insert it where it adds the least length.
+ * Placing the outliers first sketches the overall shape of the tour early. This compares every
+ * pair of nodes, so it is O(n²).
+ */
+pub fn farthest_insertion_tour<I: TspInstance>(tsp: &I) -> Vec<usize> {
+    let count = tsp.count();
+    if count < 3 {
+        return (0..count).collect();
+    }
+    let mut in_tour = vec![false; count];
+    let mut tour = vec![0];
+    in_tour[0] = true;
+    let mut nearest: Vec<u64> = (0..count).map(|i| tsp.retrieve_distance(0, i)).collect();
+    while tour.len() < count {
+        let next = (0..count)
+            .filter(|&i| !in_tour[i])
+            .max_by_key(|&i| (nearest[i], std::cmp::Reverse(i)))
+            .unwrap();
+        let position = (0..tour.len())
+            .min_by_key(|&p| {
+                let a = tour[p];
+                let b = tour[(p + 1) % tour.len()];
+                tsp.retrieve_distance(a, next) as i64 + tsp.retrieve_distance(next, b) as i64
+                    - tsp.retrieve_distance(a, b) as i64
+            })
+            .unwrap();
+        tour.insert(position + 1, next);
+        in_tour[next] = true;
+        for (i, distance) in nearest.iter_mut().enumerate() {
+            *distance = (*distance).min(tsp.retrieve_distance(next, i));
+        }
+    }
+    tour
+}
+
+// Every (i, j) with j in the neighbor list of i, or i in the list of j, once, with i < j
+fn candidate_edges<I: TspInstance>(tsp: &I) -> Vec<(usize, usize)> {
+    let mut edges: Vec<(usize, usize)> = (0..tsp.count())
+        .flat_map(|i| {
+            tsp.neighbors(i)
+                .iter()
+                .filter(move |&&j| j != i)
+                .map(move |&j| (i.min(j), i.max(j)))
+        })
+        .collect();
+    edges.sort_unstable();
+    edges.dedup();
+    edges
+}
+
+// Accepts edges in order, as long as they join the ends of two different fragments. Returns the
+// edges of each node.
+fn match_edges(count: usize, edges: impl Iterator<Item = (usize, usize)>) -> Vec<Vec<usize>> {
+    let mut adjacency = vec![Vec::with_capacity(2); count];
+    let mut fragment: Vec<usize> = (0..count).collect();
+    fn find(fragment: &mut [usize], mut i: usize) -> usize {
+        while fragment[i] != i {
+            fragment[i] = fragment[fragment[i]];
+            i = fragment[i];
+        }
+        i
+    }
+    let mut accepted = 0;
+    for (i, j) in edges {
+        if accepted + 1 >= count {
+            break;
+        }
+        if adjacency[i].len() >= 2 || adjacency[j].len() >= 2 {
+            continue;
+        }
+        let (fi, fj) = (find(&mut fragment, i), find(&mut fragment, j));
+        if fi == fj {
+            continue;
+        }
+        fragment[fj] = fi;
+        adjacency[i].push(j);
+        adjacency[j].push(i);
+        accepted += 1;
+    }
+    adjacency
+}
+
+// Walks each path fragment, then chains them together, always continuing with the fragment whose
+// nearer end is closest to the end of the tour so far
+fn join_fragments<I: TspInstance>(tsp: &I, adjacency: &[Vec<usize>]) -> Vec<usize> {
+    let count = adjacency.len();
+    let mut visited = vec![false; count];
+    let mut fragments: Vec<Vec<usize>> = Vec::new();
+    for start in 0..count {
+        if visited[start] || adjacency[start].len() > 1 {
+            continue;
+        }
+        let mut path = vec![start];
+        visited[start] = true;
+        let mut current = start;
+        while let Some(&next) = adjacency[current].iter().find(|&&n| !visited[n]) {
+            visited[next] = true;
+            path.push(next);
+            current = next;
+        }
+        fragments.push(path);
+    }
+
+    let mut tour = Vec::with_capacity(count);
+    let mut used = vec![false; fragments.len()];
+    let mut current = 0;
+    for _ in 0..fragments.len() {
+        used[current] = true;
+        tour.extend_from_slice(&fragments[current]);
+        let end = *tour.last().unwrap();
+        let closest = (0..fragments.len())
+            .filter(|&f| !used[f])
+            .map(|f| {
+                let first = tsp.retrieve_distance(end, fragments[f][0]);
+                let last = tsp.retrieve_distance(end, *fragments[f].last().unwrap());
+                (first.min(last), f, last < first)
+            })
+            .min();
+        match closest {
+            Some((_, f, reverse)) => {
+                if reverse {
+                    fragments[f].reverse();
+                }
+                current = f;
+            }
+            None => break,
+        }
+    }
+    tour
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::construction::*;
+    use crate::testutils::*;
+    use crate::tsp::Tsp;
+
+    fn check_constructions(tsp: &Tsp<u8>) -> Vec<Vec<usize>> {
+        let tours = vec![
+            greedy_edge_tour(tsp),
+            savings_tour(tsp),
+            farthest_insertion_tour(tsp),
+        ];
+        for tour in &tours {
+            check_permutation(tour, tsp.count - 1);
+        }
+        tours
+    }
+
+    #[test]
+    fn constructions_solve_linear_distances() {
+        let tsp = Tsp::new(build_linear_distances(20), 20);
+        for tour in check_constructions(&tsp) {
+            // The best cycle goes out along the line and comes straight back
+            let mut cycle = tour.clone();
+            cycle.push(tour[0]);
+            assert_eq!(38, tsp.calculate_distance(&cycle), "{:?}", tour);
+        }
+    }
+
+    #[test]
+    fn constructions_return_permutations() {
+        let tsp = Tsp::new(build_random_distances(50), 50);
+        check_constructions(&tsp);
+        let tsp = Tsp::new(build_geo_sample(), 7);
+        check_constructions(&tsp);
+    }
+
+    #[test]
+    fn constructions_beat_identity_on_geometric_instance() {
+        let mut points = Vec::new();
+        for i in 0..60usize {
+            points.push(((i * 37 % 61 * 4) as u8, (i * 11 % 59 * 4) as u8));
+        }
+        let distances = build_geometric_distances::<u32>(&points);
+        let tsp = Tsp::new(distances, points.len());
+        let identity: Vec<usize> = (0..points.len()).collect();
+        let cycle_length = |tour: &Vec<usize>| {
+            let mut cycle = tour.clone();
+            cycle.push(tour[0]);
+            tsp.calculate_distance(&cycle)
+        };
+        for tour in [
+            greedy_edge_tour(&tsp),
+            savings_tour(&tsp),
+            farthest_insertion_tour(&tsp),
+        ] {
+            check_permutation(&tour, points.len() - 1);
+            assert!(cycle_length(&tour) < cycle_length(&identity));
+        }
+    }
+}
@@ -0,0 +1,125 @@
+use num_traits::int::PrimInt;
+
+// 64x64 tiles of u8 distances fill a 4KiB page
+const DEFAULT_BLOCK_SHIFT: u32 = 6;
+
+/// Symmetric distances, storing only the upper triangle (including the diagonal) of the matrix.
+///
+/// In the packed layout, rows of the triangle are stored one after another. The blocked layout
+/// instead divides the matrix into square tiles and stores the tiles of the upper triangle one
+/// after another, so a scan over all distances from one node touches one tile per `block_size`
+/// nodes, whichever side of the diagonal they're on. The tiles on the diagonal are stored whole,
+/// so the blocked layout uses about `count * block_size / 2` more entries than the packed one.
+#[derive(Clone, Debug)]
+pub struct TriangularDistances<T: PrimInt> {
+    distances: Vec<T>,
+    count: usize,
+    // log2 of the tile size, 0 for the packed layout
+    block_shift: u32,
+    blocks: usize,
+}
+
+impl<T: PrimInt> TriangularDistances<T> {
+    pub fn packed(count: usize) -> TriangularDistances<T> {
+        TriangularDistances::with_block_shift(count, 0)
+    }
+
+    pub fn blocked(count: usize) -> TriangularDistances<T> {
+        TriangularDistances::with_block_shift(count, DEFAULT_BLOCK_SHIFT)
+    }
+
+    fn with_block_shift(count: usize, block_shift: u32) -> TriangularDistances<T> {
+        let block_size = 1 << block_shift;
+        let blocks = count.div_ceil(block_size);
+        let size = (blocks * (blocks + 1) / 2) << (2 * block_shift);
+        TriangularDistances {
+            distances: vec![T::zero(); size],
+            count,
+            block_shift,
+            blocks,
+        }
+    }
+
+    /// Packs a full, symmetric `count * count` matrix. Only the upper triangle is read.
+    pub fn from_full(full: &[T], count: usize) -> TriangularDistances<T> {
+        assert!(full.len() == count * count);
+        let mut distances = TriangularDistances::packed(count);
+        for i in 0..count {
+            for j in i..count {
+                distances.set(i, j, full[i * count + j]);
+            }
+        }
+        distances
+    }
+
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    pub fn get(&self, t1: usize, t2: usize) -> T {
+        self.distances[self.index(t1, t2)]
+    }
+
+    pub fn set(&mut self, t1: usize, t2: usize, distance: T) {
+        let index = self.index(t1, t2);
+        self.distances[index] = distance;
+    }
+
+    fn index(&self, t1: usize, t2: usize) -> usize {
+        let (low, high) = if t1 < t2 { (t1, t2) } else { (t2, t1) };
+        let shift = self.block_shift;
+        let mask = (1 << shift) - 1;
+        let block_row = low >> shift;
+        let block_column = high >> shift;
+        let block = block_row * (2 * self.blocks - block_row + 1) / 2 + (block_column - block_row);
+        (block << (2 * shift)) | ((low & mask) << shift) | (high & mask)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::distances::*;
+    use crate::testutils::*;
+
+    use proptest::prelude::*;
+
+    #[test]
+    fn packed_layout_halves_storage() {
+        let distances: TriangularDistances<u8> = TriangularDistances::packed(10000);
+        assert_eq!(10000 * 10001 / 2, distances.distances.len());
+    }
+
+    #[test]
+    fn from_full_matches_full_matrix() {
+        let full = build_random_distances(37);
+        let distances = TriangularDistances::from_full(&full, 37);
+        for i in 0..37 {
+            for j in 0..37 {
+                assert_eq!(full[i * 37 + j], distances.get(i, j));
+            }
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn layouts_store_distinct_pairs_separately(count in 1usize..200) {
+            let layouts = [
+                TriangularDistances::<u32>::packed(count),
+                TriangularDistances::blocked(count),
+            ];
+            for mut distances in layouts {
+                for i in 0..count {
+                    for j in i..count {
+                        distances.set(i, j, (i * count + j) as u32);
+                    }
+                }
+                for i in 0..count {
+                    for j in 0..count {
+                        let expected = (i.min(j) * count + i.max(j)) as u32;
+                        prop_assert_eq!(expected, distances.get(i, j));
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,495 @@
+use crate::construction::greedy_edge_tour;
+use crate::tour::Tour;
+use crate::tsp::TspInstance;
+use crate::twoopt::optimize_twoopt_from_tour;
+
+// Held-Karp takes O(2^n n²) time and O(2^n n) memory
+pub const HELD_KARP_LIMIT: usize = 16;
+// Branch and bound is much faster than Held-Karp on typical instances, but has no useful worst
+// case bound, so it gives up after SEARCH_BUDGET search nodes
+pub const BRANCH_AND_BOUND_LIMIT: usize = 32;
+const SEARCH_BUDGET: usize = 200_000;
+const SUBGRADIENT_ITERATIONS: usize = 50;
+
+/**
+ * Finds the shortest open path through every node, optionally starting and ending at given nodes.
+ * Returns None if the instance is too large to solve exactly, or branch and bound runs out of
+ * budget.
+ *
+ * Paths are solved as cycles through one extra node. Its edges to nodes that may end the path are
+ * free, and its other edges cost more than any path, so the optimal cycle passes through the extra
+ * node between the two ends of the optimal path.
+ */
+pub fn solve_path<I: TspInstance>(
+    tsp: &I,
+    start: Option<usize>,
+    end: Option<usize>,
+) -> Option<Vec<usize>> {
+    let count = tsp.count();
+    if count > BRANCH_AND_BOUND_LIMIT {
+        return None;
+    }
+    if count < 3 {
+        let mut path: Vec<usize> = (0..count).collect();
+        if start == Some(1) || end == Some(0) {
+            path.reverse();
+        }
+        return Some(path);
+    }
+    let longest = (0..count)
+        .flat_map(|i| (0..count).map(move |j| (i, j)))
+        .map(|(i, j)| tsp.retrieve_distance(i, j))
+        .max()
+        .unwrap();
+    let expensive = (count as u64 + 1) * longest + 1;
+    let extra_edge = |node: usize| {
+        let mut cost = 0;
+        for pinned in [start, end].into_iter().flatten() {
+            if pinned != node {
+                cost += expensive;
+            }
+        }
+        cost
+    };
+    let mut distances = vec![0; (count + 1) * (count + 1)];
+    for i in 0..=count {
+        for j in 0..=count {
+            distances[i * (count + 1) + j] = match (i == count, j == count) {
+                (true, true) => 0,
+                (true, false) => extra_edge(j),
+                (false, true) => extra_edge(i),
+                (false, false) => tsp.retrieve_distance(i, j),
+            };
+        }
+    }
+    let cycle = solve_matrix(Matrix::new(distances, count + 1))?;
+
+    // Cut the cycle at the extra node, and orient it so a pinned start comes first
+    let extra = cycle.iter().position(|&n| n == count).unwrap();
+    let mut path: Vec<usize> = cycle[extra + 1..]
+        .iter()
+        .chain(&cycle[..extra])
+        .copied()
+        .collect();
+    if start.is_some_and(|s| path[0] != s) || end.is_some_and(|e| path[0] == e) {
+        path.reverse();
+    }
+    Some(path)
+}
+
+/// Finds the shortest cycle through every node, or None like solve_path.
+pub fn solve_cycle<I: TspInstance>(tsp: &I) -> Option<Vec<usize>> {
+    let count = tsp.count();
+    if count > BRANCH_AND_BOUND_LIMIT {
+        return None;
+    }
+    if count < 3 {
+        return Some((0..count).collect());
+    }
+    let distances = (0..count)
+        .flat_map(|i| (0..count).map(move |j| (i, j)))
+        .map(|(i, j)| tsp.retrieve_distance(i, j))
+        .collect();
+    solve_matrix(Matrix::new(distances, count))
+}
+
+fn solve_matrix(matrix: Matrix) -> Option<Vec<usize>> {
+    if matrix.count <= HELD_KARP_LIMIT + 1 {
+        Some(held_karp(&matrix))
+    } else {
+        branch_and_bound(&matrix)
+    }
+}
+
+// A small dense instance, so the exact solvers can work on any TspInstance and its augmented form
+struct Matrix {
+    count: usize,
+    distances: Vec<u64>,
+    neighbors: Vec<Vec<usize>>,
+}
+
+impl Matrix {
+    fn new(distances: Vec<u64>, count: usize) -> Matrix {
+        let neighbors = (0..count)
+            .map(|i| {
+                let mut others: Vec<usize> = (0..count).filter(|&j| j != i).collect();
+                others.sort_by_key(|&j| distances[i * count + j]);
+                others
+            })
+            .collect();
+        Matrix {
+            count,
+            distances,
+            neighbors,
+        }
+    }
+}
+
+impl TspInstance for Matrix {
+    fn count(&self) -> usize {
+        self.count
+    }
+
+    fn retrieve_distance(&self, t1: usize, t2: usize) -> u64 {
+        self.distances[t1 * self.count + t2]
+    }
+
+    fn neighbors(&self, index: usize) -> &[usize] {
+        &self.neighbors[index]
+    }
+}
+
+/**
+ * Held-Karp dynamic programming. best[mask][j] is the length of the shortest path that starts
+ * at node 0, visits the nodes in mask, and ends at node j. The optimal cycle is recovered by
+ * walking back from the cheapest full path.
+ */
+fn held_karp(matrix: &Matrix) -> Vec<usize> {
+    let others = matrix.count - 1;
+    let d = |a: usize, b: usize| matrix.retrieve_distance(a, b);
+    // Node k + 1 is bit k
+    let full = (1usize << others) - 1;
+    let mut best = vec![u64::MAX; (full + 1) * others];
+    for j in 0..others {
+        best[(1 << j) * others + j] = d(0, j + 1);
+    }
+    for mask in 1..=full {
+        for j in 0..others {
+            let current = best[mask * others + j];
+            if current == u64::MAX {
+                continue;
+            }
+            for k in 0..others {
+                if mask & (1 << k) != 0 {
+                    continue;
+                }
+                let next = mask | (1 << k);
+                let candidate = current + d(j + 1, k + 1);
+                if candidate < best[next * others + k] {
+                    best[next * others + k] = candidate;
+                }
+            }
+        }
+    }
+
+    let mut last = (0..others)
+        .min_by_key(|&j| best[full * others + j] + d(j + 1, 0))
+        .unwrap();
+    let mut mask = full;
+    let mut reversed = vec![last + 1];
+    while mask != 1 << last {
+        let previous_mask = mask & !(1 << last);
+        let previous = (0..others)
+            .filter(|&i| previous_mask & (1 << i) != 0)
+            .find(|&i| {
+                best[previous_mask * others + i] + d(i + 1, last + 1) == best[mask * others + last]
+            })
+            .unwrap();
+        reversed.push(previous + 1);
+        mask = previous_mask;
+        last = previous;
+    }
+    reversed.push(0);
+    reversed.reverse();
+    reversed
+}
+
+/**
+ * Depth first branch and bound, extending a path from node 0 one node at a time. A path that
+ * completes the cycle from the last node back to 0 through the remaining nodes is a spanning tree
+ * of those nodes, so the minimum spanning tree bounds what is left.
+ *
+ * To tighten that bound, we first run subgradient optimization on the 1-tree bound of the whole
+ * instance (Held and Karp, 1970). This gives node penalties pi, and we search with distances
+ * d(i, j) + pi(i) + pi(j), which adds the same 2 * sum(pi) to every cycle, so the optimal cycle
+ * doesn't change but spanning trees become much closer to cycles.
+ */
+fn branch_and_bound(matrix: &Matrix) -> Option<Vec<usize>> {
+    let count = matrix.count;
+    let initial = optimize_twoopt_from_tour(matrix, Tour::new(greedy_edge_tour(matrix))).ok()?;
+    let upper_bound = cycle_length(matrix, &initial) as f64;
+    let penalties = subgradient(matrix, upper_bound);
+    let penalized: Vec<f64> = (0..count * count)
+        .map(|index| {
+            let (i, j) = (index / count, index % count);
+            matrix.distances[index] as f64 + penalties[i] + penalties[j]
+        })
+        .collect();
+    let best = cycle_length(matrix, &initial) as f64 + 2.0 * penalties.iter().sum::<f64>();
+
+    let mut search = Search {
+        count,
+        distances: penalized,
+        best,
+        best_cycle: initial,
+        nodes: 0,
+    };
+    let mut path = vec![0];
+    let mut in_path = vec![false; count];
+    in_path[0] = true;
+    if search.extend(&mut path, &mut in_path, 0.0) {
+        log::debug!(
+            "Branch and bound finished after {} nodes, distance={}",
+            search.nodes,
+            cycle_length(matrix, &search.best_cycle)
+        );
+        Some(search.best_cycle)
+    } else {
+        log::debug!("Branch and bound ran out of budget on {} nodes", count);
+        None
+    }
+}
+
+fn cycle_length(matrix: &Matrix, cycle: &[usize]) -> u64 {
+    matrix.calculate_distance(cycle) + matrix.retrieve_distance(cycle[cycle.len() - 1], cycle[0])
+}
+
+struct Search {
+    count: usize,
+    distances: Vec<f64>,
+    best: f64,
+    best_cycle: Vec<usize>,
+    nodes: usize,
+}
+
+impl Search {
+    // Returns false if the search ran out of budget
+    fn extend(&mut self, path: &mut Vec<usize>, in_path: &mut [bool], length: f64) -> bool {
+        self.nodes += 1;
+        if self.nodes > SEARCH_BUDGET {
+            return false;
+        }
+        let count = self.count;
+        let last = *path.last().unwrap();
+        if path.len() == count {
+            let total = length + self.distances[last * count];
+            if total < self.best - 0.5 {
+                self.best = total;
+                self.best_cycle = path.clone();
+            }
+            return true;
+        }
+        let mut remaining: Vec<usize> = (0..count).filter(|&i| !in_path[i]).collect();
+        let mut tree_nodes = remaining.clone();
+        tree_nodes.push(last);
+        if last != 0 {
+            tree_nodes.push(0);
+        }
+        // Original distances are integers, so a shorter cycle is shorter by at least 1
+        if length + spanning_tree(&self.distances, count, &tree_nodes) > self.best - 1.0 + 1e-6 {
+            return true;
+        }
+        remaining.sort_by(|&a, &b| {
+            self.distances[last * count + a].total_cmp(&self.distances[last * count + b])
+        });
+        for next in remaining {
+            path.push(next);
+            in_path[next] = true;
+            let extended = length + self.distances[last * count + next];
+            let finished = self.extend(path, in_path, extended);
+            in_path[next] = false;
+            path.pop();
+            if !finished {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+// Prim's algorithm over a subset of the nodes, returning the weight of the tree
+fn spanning_tree(distances: &[f64], count: usize, nodes: &[usize]) -> f64 {
+    spanning_tree_edges(distances, count, nodes)
+        .iter()
+        .map(|&(a, b)| distances[a * count + b])
+        .sum()
+}
+
+fn spanning_tree_edges(distances: &[f64], count: usize, nodes: &[usize]) -> Vec<(usize, usize)> {
+    let mut edges = Vec::with_capacity(nodes.len());
+    if nodes.is_empty() {
+        return edges;
+    }
+    let mut in_tree = vec![false; nodes.len()];
+    // (distance to the tree, closest tree node) for each node
+    let mut closest: Vec<(f64, usize)> = nodes
+        .iter()
+        .map(|&n| (distances[nodes[0] * count + n], nodes[0]))
+        .collect();
+    in_tree[0] = true;
+    for _ in 1..nodes.len() {
+        let next = (0..nodes.len())
+            .filter(|&i| !in_tree[i])
+            .min_by(|&a, &b| closest[a].0.total_cmp(&closest[b].0))
+            .unwrap();
+        in_tree[next] = true;
+        edges.push((closest[next].1, nodes[next]));
+        for i in 0..nodes.len() {
+            let distance = distances[nodes[next] * count + nodes[i]];
+            if !in_tree[i] && distance < closest[i].0 {
+                closest[i] = (distance, nodes[next]);
+            }
+        }
+    }
+    edges
+}
+
+// Node penalties that maximize the 1-tree lower bound: a spanning tree over nodes 1..n, plus the
+// two shortest edges from node 0. Nodes with too many tree edges get more expensive, and leaves
+// get cheaper, which pushes the 1-tree towards being a cycle.
+fn subgradient(matrix: &Matrix, upper_bound: f64) -> Vec<f64> {
+    let count = matrix.count;
+    let mut penalties = vec![0.0; count];
+    let mut best_penalties = penalties.clone();
+    let mut best_bound = f64::MIN;
+    let mut step_scale = 2.0;
+    let others: Vec<usize> = (1..count).collect();
+    for iteration in 0..SUBGRADIENT_ITERATIONS {
+        let penalized: Vec<f64> = (0..count * count)
+            .map(|index| {
+                matrix.distances[index] as f64 + penalties[index / count] + penalties[index % count]
+            })
+            .collect();
+        let mut degrees = vec![0i64; count];
+        let mut weight = 0.0;
+        for (a, b) in spanning_tree_edges(&penalized, count, &others) {
+            degrees[a] += 1;
+            degrees[b] += 1;
+            weight += penalized[a * count + b];
+        }
+        let mut from_start: Vec<usize> = others.clone();
+        from_start.sort_by(|&a, &b| penalized[a].total_cmp(&penalized[b]));
+        for &n in &from_start[..2] {
+            degrees[0] += 1;
+            degrees[n] += 1;
+            weight += penalized[n];
+        }
+        let bound = weight - 2.0 * penalties.iter().sum::<f64>();
+        if bound > best_bound {
+            best_bound = bound;
+            best_penalties = penalties.clone();
+        }
+        let norm: i64 = degrees.iter().map(|d| (d - 2) * (d - 2)).sum();
+        if norm == 0 {
+            // The 1-tree is a cycle, so it's optimal
+            break;
+        }
+        if iteration % 10 == 9 {
+            step_scale /= 2.0;
+        }
+        let step = step_scale * (upper_bound - bound).max(1.0) / norm as f64;
+        for i in 0..count {
+            penalties[i] += step * (degrees[i] - 2) as f64;
+        }
+    }
+    best_penalties
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::exact::*;
+    use crate::testutils::*;
+    use crate::tsp::Tsp;
+    use crate::twoopt::optimize_twoopt_path;
+
+    // Tries every order, for checking the solvers on small instances
+    fn brute_force<I: TspInstance>(tsp: &I, cycle: bool) -> u64 {
+        fn permute<I: TspInstance>(
+            tsp: &I,
+            cycle: bool,
+            path: &mut Vec<usize>,
+            used: &mut Vec<bool>,
+        ) -> u64 {
+            if path.len() == tsp.count() {
+                let closing = if cycle {
+                    tsp.retrieve_distance(path[path.len() - 1], path[0])
+                } else {
+                    0
+                };
+                return tsp.calculate_distance(path) + closing;
+            }
+            let mut best = u64::MAX;
+            for next in 0..tsp.count() {
+                if !used[next] {
+                    used[next] = true;
+                    path.push(next);
+                    best = best.min(permute(tsp, cycle, path, used));
+                    path.pop();
+                    used[next] = false;
+                }
+            }
+            best
+        }
+        permute(tsp, cycle, &mut Vec::new(), &mut vec![false; tsp.count()])
+    }
+
+    fn cycle_distance<I: TspInstance>(tsp: &I, cycle: &[usize]) -> u64 {
+        tsp.calculate_distance(cycle) + tsp.retrieve_distance(cycle[cycle.len() - 1], cycle[0])
+    }
+
+    #[test]
+    fn held_karp_matches_brute_force() {
+        for seed_size in [5, 7, 8] {
+            let tsp = Tsp::new(build_random_distances(seed_size), seed_size);
+            let cycle = solve_cycle(&tsp).unwrap();
+            check_permutation(&cycle, seed_size - 1);
+            assert_eq!(brute_force(&tsp, true), cycle_distance(&tsp, &cycle));
+            let path = solve_path(&tsp, None, None).unwrap();
+            check_permutation(&path, seed_size - 1);
+            assert_eq!(brute_force(&tsp, false), tsp.calculate_distance(&path));
+        }
+    }
+
+    #[test]
+    fn solve_path_keeps_pinned_ends() {
+        let tsp = Tsp::new(build_linear_distances(8), 8);
+        let path = solve_path(&tsp, Some(3), None).unwrap();
+        assert_eq!(3, path[0]);
+        assert_eq!(3 + 7, tsp.calculate_distance(&path));
+        let path = solve_path(&tsp, None, Some(5)).unwrap();
+        assert_eq!(5, path[7]);
+        let path = solve_path(&tsp, Some(2), Some(6)).unwrap();
+        assert_eq!((2, 6), (path[0], path[7]));
+        check_permutation(&path, 7);
+    }
+
+    #[test]
+    fn branch_and_bound_matches_held_karp() {
+        let tsp = Tsp::new(build_random_distances(14), 14);
+        let distances = (0..14)
+            .flat_map(|i| (0..14).map(move |j| (i, j)))
+            .map(|(i, j)| tsp.retrieve_distance(i, j))
+            .collect();
+        let matrix = Matrix::new(distances, 14);
+        let exact = held_karp(&matrix);
+        let searched = branch_and_bound(&matrix).unwrap();
+        check_permutation(&searched, 13);
+        assert_eq!(
+            cycle_length(&matrix, &exact),
+            cycle_length(&matrix, &searched)
+        );
+    }
+
+    #[test]
+    fn branch_and_bound_solves_geometric_instance() {
+        let mut points = Vec::new();
+        for i in 0..24usize {
+            points.push(((i * 37 % 61 * 4) as u8, (i * 11 % 59 * 4) as u8));
+        }
+        let tsp: Tsp<u32> = Tsp::new(build_geometric_distances(&points), 24);
+        let cycle = solve_cycle(&tsp).unwrap();
+        check_permutation(&cycle, 23);
+        // 2-opt is a heuristic, so it can only match the exact solution
+        let heuristic = optimize_twoopt_from_tour(&tsp, Tour::new((0..24).collect())).unwrap();
+        assert!(cycle_distance(&tsp, &cycle) <= cycle_distance(&tsp, &heuristic));
+    }
+
+    #[test]
+    fn path_heuristic_is_no_better_than_exact() {
+        let tsp = Tsp::new(build_random_distances(12), 12);
+        let exact = solve_path(&tsp, None, None).unwrap();
+        let heuristic =
+            optimize_twoopt_path(&tsp, Tour::new((0..12).collect()), None, None).unwrap();
+        assert!(tsp.calculate_distance(&exact) <= tsp.calculate_distance(&heuristic));
+    }
+}
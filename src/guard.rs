@@ -0,0 +1,238 @@
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+
+use crate::compress::{CompressorSetting, ConcatenatedFiles};
+use crate::lz77::estimate_compressed_size;
+
+// A sample is taken as this many runs of consecutive files, spread evenly over the ordering, so
+// that it sees the start, middle and end of the archive rather than just the start
+const SAMPLE_RUNS: u64 = 8;
+
+/// How the guard decides which of two orderings compresses better.
+#[derive(Debug, Clone, Copy)]
+pub enum GuardScorer {
+    /// The in-crate LZ77 estimate, with this window
+    Estimate(usize),
+    /// Actually running the compressor
+    Trial(CompressorSetting),
+}
+
+/**
+ * Checks an optimized ordering against the order the files were found in, which is the order tar
+ * would have archived them in, and keeps whichever compresses better. Ties go to the natural
+ * order, so turning simsort on never makes an archive bigger than plain tar, as far as the scorer
+ * can tell.
+ *
+ * Large orderings are scored on a sample: runs of files taken from the natural order, compressed
+ * once in that order and once in the order the optimized ordering puts them in. Both score the
+ * same files, so the difference is down to their order, not to which files each happened to pick.
+ */
+pub struct OrderGuard {
+    scorer: GuardScorer,
+    sample_size: u64,
+    // Where each path was found in the walk of the directory
+    ranks: HashMap<PathBuf, usize>,
+}
+
+impl OrderGuard {
+    pub fn new(scorer: GuardScorer, sample_size: u64, walk: &[PathBuf]) -> OrderGuard {
+        OrderGuard {
+            scorer,
+            sample_size,
+            ranks: walk
+                .iter()
+                .enumerate()
+                .map(|(rank, path)| (path.clone(), rank))
+                .collect(),
+        }
+    }
+
+    /// Returns `optimized`, unless the same files compress at least as well in their natural
+    /// order, in which case it returns them in that order. `label` names the files in the log.
+    pub fn choose(&self, optimized: Vec<PathBuf>, label: &str) -> Result<Vec<PathBuf>, String> {
+        if optimized.len() < 2 {
+            return Ok(optimized);
+        }
+        let natural = self.natural_order(&optimized);
+        if natural == optimized {
+            return Ok(optimized);
+        }
+        let (natural_sample, raw_size) = sample(&natural, self.sample_size);
+        let sampled: HashSet<&PathBuf> = natural_sample.iter().collect();
+        let optimized_sample: Vec<PathBuf> = optimized
+            .iter()
+            .filter(|path| sampled.contains(path))
+            .cloned()
+            .collect();
+        let natural_score = self.score(&natural_sample, raw_size)?;
+        let optimized_score = self.score(&optimized_sample, raw_size)?;
+        if optimized_score < natural_score {
+            log::info!(
+                "Guard kept the optimized order for {}: ratio {:.4}, natural order {:.4}",
+                label,
+                optimized_score,
+                natural_score
+            );
+            Ok(optimized)
+        } else {
+            log::info!(
+                "Guard fell back to the natural order for {}: ratio {:.4}, optimized order {:.4}",
+                label,
+                natural_score,
+                optimized_score
+            );
+            Ok(natural)
+        }
+    }
+
+    fn natural_order(&self, paths: &[PathBuf]) -> Vec<PathBuf> {
+        let mut natural = paths.to_vec();
+        natural.sort_by_key(|path| self.ranks.get(path).copied().unwrap_or(usize::MAX));
+        natural
+    }
+
+    // Compressed bytes per input byte of the files, in order
+    fn score(&self, paths: &[PathBuf], raw_size: u64) -> Result<f64, String> {
+        if raw_size == 0 {
+            return Ok(0.0);
+        }
+        let compressed_size = match self.scorer {
+            GuardScorer::Estimate(window) => estimate_compressed_size(paths, window)?,
+            GuardScorer::Trial(setting) => setting.measure(ConcatenatedFiles::new(paths))?.0,
+        };
+        Ok(compressed_size as f64 / raw_size as f64)
+    }
+}
+
+/// Picks runs of consecutive files, spread over the ordering, adding up to about `budget` bytes.
+/// Returns the files in order and their total size.
+pub fn sample(paths: &[PathBuf], budget: u64) -> (Vec<PathBuf>, u64) {
+    let sizes: Vec<u64> = paths
+        .iter()
+        .map(|path| match path.metadata() {
+            Ok(metadata) if metadata.is_file() => metadata.len(),
+            _ => 0,
+        })
+        .collect();
+    let total: u64 = sizes.iter().sum();
+    if total <= budget {
+        return (paths.to_vec(), total);
+    }
+    let count = paths.len() as u64;
+    let per_run = budget / SAMPLE_RUNS;
+    let mut sampled = Vec::new();
+    let mut sampled_size = 0;
+    for run in 0..SAMPLE_RUNS {
+        let start = (run * count / SAMPLE_RUNS) as usize;
+        let end = ((run + 1) * count / SAMPLE_RUNS) as usize;
+        let mut run_size = 0;
+        for i in start..end {
+            if run_size >= per_run {
+                break;
+            }
+            sampled.push(paths[i].clone());
+            run_size += sizes[i];
+        }
+        sampled_size += run_size;
+    }
+    (sampled, sampled_size)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use rand::rngs::StdRng;
+    use rand::{RngCore, SeedableRng};
+    use tempfile::{tempdir, TempDir};
+
+    use crate::guard::*;
+    use crate::lz77::GZIP_WINDOW;
+
+    // Files a, b and c, where c is a copy of a with one byte changed, so a and c belong together
+    fn setup_files() -> (TempDir, Vec<PathBuf>) {
+        let tempdir = tempdir().unwrap();
+        let mut rng = StdRng::seed_from_u64(43);
+        let mut a = vec![0; 20000];
+        rng.fill_bytes(&mut a);
+        let mut b = vec![0; 20000];
+        rng.fill_bytes(&mut b);
+        let mut c = a.clone();
+        c[100] ^= 1;
+        let paths: Vec<PathBuf> = ["a", "b", "c"]
+            .iter()
+            .map(|name| tempdir.path().join(name))
+            .collect();
+        for (path, contents) in paths.iter().zip([a, b, c]) {
+            fs::write(path, contents).unwrap();
+        }
+        (tempdir, paths)
+    }
+
+    #[test]
+    fn guard_falls_back_to_natural_order() {
+        let (_tempdir, paths) = setup_files();
+        let walk = vec![paths[0].clone(), paths[2].clone(), paths[1].clone()];
+        let guard = OrderGuard::new(GuardScorer::Estimate(GZIP_WINDOW), 1 << 20, &walk);
+        let chosen = guard.choose(paths.clone(), "test").unwrap();
+        assert_eq!(walk, chosen);
+    }
+
+    #[test]
+    fn guard_keeps_better_order() {
+        let (_tempdir, paths) = setup_files();
+        let better = vec![paths[1].clone(), paths[0].clone(), paths[2].clone()];
+        let guard = OrderGuard::new(GuardScorer::Estimate(GZIP_WINDOW), 1 << 20, &paths);
+        assert_eq!(better, guard.choose(better.clone(), "test").unwrap());
+        let trial = OrderGuard::new(
+            GuardScorer::Trial("gzip:6".parse().unwrap()),
+            1 << 20,
+            &paths,
+        );
+        assert_eq!(better, trial.choose(better.clone(), "test").unwrap());
+    }
+
+    #[test]
+    fn guard_compares_the_same_sample_in_both_orders() {
+        // Eight files and a copy of each, with every copy far outside gzip's window of its
+        // original in the natural order, and right after it in the optimized order
+        let tempdir = tempdir().unwrap();
+        let mut rng = StdRng::seed_from_u64(44);
+        let mut originals = Vec::new();
+        let mut copies = Vec::new();
+        for i in 0..8 {
+            let mut contents = vec![0; 20000];
+            rng.fill_bytes(&mut contents);
+            for (paths, name) in [(&mut originals, "original"), (&mut copies, "copy")] {
+                let path = tempdir.path().join(format!("{}{}", name, i));
+                fs::write(&path, &contents).unwrap();
+                paths.push(path);
+            }
+        }
+        let walk: Vec<PathBuf> = originals.iter().chain(&copies).cloned().collect();
+        let optimized: Vec<PathBuf> = originals
+            .iter()
+            .zip(&copies)
+            .flat_map(|(original, copy)| [original.clone(), copy.clone()])
+            .collect();
+        // Half the files fit in the sample, so sampling each order separately would pick only
+        // originals from the optimized order, with nothing to match
+        let guard = OrderGuard::new(GuardScorer::Estimate(GZIP_WINDOW), 160000, &walk);
+        assert_eq!(optimized, guard.choose(optimized.clone(), "test").unwrap());
+    }
+
+    #[test]
+    fn sample_spreads_over_ordering() {
+        let (_tempdir, paths) = setup_files();
+        let (sampled, size) = sample(&paths, 1 << 20);
+        assert_eq!(paths, sampled);
+        assert_eq!(60000, size);
+
+        let many: Vec<PathBuf> = paths.iter().cycle().take(80).cloned().collect();
+        let (sampled, size) = sample(&many, 160000);
+        assert_eq!(8, sampled.len());
+        assert_eq!(160000, size);
+        assert_eq!(many[10], sampled[1]);
+        assert_eq!(many[70], sampled[7]);
+    }
+}
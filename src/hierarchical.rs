@@ -0,0 +1,368 @@
+use num_traits::int::PrimInt;
+
+use crate::distances::TriangularDistances;
+
+/// One step of agglomerative clustering. Nodes `0..count` are the leaves, and node `count + i` is
+/// the cluster created by merge `i`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Merge {
+    pub left: usize,
+    pub right: usize,
+    pub distance: f64,
+}
+
+/// The result of average-linkage (UPGMA) clustering over a distance matrix. Merges are stored in
+/// the order they were created, so children always come before their parents.
+#[derive(Debug)]
+pub struct Dendrogram {
+    pub count: usize,
+    pub merges: Vec<Merge>,
+}
+
+impl Dendrogram {
+    /**
+     * Clusters with the nearest-neighbor chain algorithm, which takes O(n²) time. Average linkage
+     * is reducible, so merging mutual nearest neighbors as we find them gives the same tree as
+     * always merging the globally closest pair.
+     */
+    pub fn average_linkage<T: PrimInt>(distances: &TriangularDistances<T>) -> Dendrogram {
+        let count = distances.count();
+        let mut matrix: Vec<f64> = vec![0.0; count * count];
+        for i in 0..count {
+            for j in 0..count {
+                matrix[i * count + j] = distances.get(i, j).to_f64().unwrap();
+            }
+        }
+        // Each slot holds an active cluster, a merged cluster reuses the slot of its left child
+        let mut active = vec![true; count];
+        let mut node = (0..count).collect::<Vec<usize>>();
+        let mut size = vec![1usize; count];
+        let mut merges = Vec::with_capacity(count.saturating_sub(1));
+        let mut chain: Vec<usize> = Vec::new();
+
+        while merges.len() + 1 < count {
+            if chain.is_empty() {
+                chain.push(active.iter().position(|&a| a).unwrap());
+            }
+            let a = *chain.last().unwrap();
+            let previous = if chain.len() >= 2 {
+                Some(chain[chain.len() - 2])
+            } else {
+                None
+            };
+            // Prefer the previous chain element on ties, so the chain can't cycle
+            let mut b = previous.unwrap_or(usize::MAX);
+            let mut best = previous.map_or(f64::INFINITY, |p| matrix[a * count + p]);
+            for c in 0..count {
+                if active[c] && c != a && matrix[a * count + c] < best {
+                    best = matrix[a * count + c];
+                    b = c;
+                }
+            }
+            if Some(b) != previous {
+                chain.push(b);
+                continue;
+            }
+            chain.pop();
+            chain.pop();
+            merges.push(Merge {
+                left: node[a],
+                right: node[b],
+                distance: best,
+            });
+            let merged_size = size[a] + size[b];
+            for c in 0..count {
+                if active[c] && c != a && c != b {
+                    let updated = (size[a] as f64 * matrix[a * count + c]
+                        + size[b] as f64 * matrix[b * count + c])
+                        / merged_size as f64;
+                    matrix[a * count + c] = updated;
+                    matrix[c * count + a] = updated;
+                }
+            }
+            active[b] = false;
+            size[a] = merged_size;
+            node[a] = count + merges.len() - 1;
+        }
+        Dendrogram { count, merges }
+    }
+
+    /// Cuts the tree into (at most) `clusters` clusters, returning the cluster of each leaf.
+    /// Clusters are numbered in order of their first leaf.
+    pub fn clusters(&self, clusters: usize) -> Vec<usize> {
+        let mut parent: Vec<usize> = (0..self.count).collect();
+        fn find(parent: &mut [usize], mut i: usize) -> usize {
+            while parent[i] != i {
+                parent[i] = parent[parent[i]];
+                i = parent[i];
+            }
+            i
+        }
+        // Any leaf under a node stands in for the whole node
+        let mut representative: Vec<usize> = (0..self.count).collect();
+        for merge in &self.merges {
+            representative.push(representative[merge.left]);
+        }
+        let mut order: Vec<usize> = (0..self.merges.len()).collect();
+        order.sort_by(|&a, &b| {
+            self.merges[a]
+                .distance
+                .partial_cmp(&self.merges[b].distance)
+                .unwrap()
+                .then(a.cmp(&b))
+        });
+        let merge_count = self.count.saturating_sub(clusters.max(1));
+        for &m in order.iter().take(merge_count) {
+            let left = find(&mut parent, representative[self.merges[m].left]);
+            let right = find(&mut parent, representative[self.merges[m].right]);
+            parent[right] = left;
+        }
+
+        let mut labels = vec![usize::MAX; self.count];
+        let mut assignments = Vec::with_capacity(self.count);
+        let mut next_label = 0;
+        for i in 0..self.count {
+            let root = find(&mut parent, i);
+            if labels[root] == usize::MAX {
+                labels[root] = next_label;
+                next_label += 1;
+            }
+            assignments.push(labels[root]);
+        }
+        assignments
+    }
+
+    /**
+     * Orders the leaves so that the sum of distances between adjacent leaves is as small as
+     * possible, among all orders consistent with the tree (Bar-Joseph et al., 2001). Each merge
+     * can put either child first, and within each subtree we know the cheapest path between
+     * every pair of leaves that could be its ends. Takes O(n³) time and O(n²) memory.
+     */
+    pub fn optimal_leaf_order<T: PrimInt>(&self, distances: &TriangularDistances<T>) -> Vec<usize> {
+        let count = self.count;
+        if count <= 2 {
+            return (0..count).collect();
+        }
+        let d = |a: usize, b: usize| distances.get(a, b).to_u64().unwrap();
+        let mut leaves: Vec<Vec<usize>> = (0..count).map(|i| vec![i]).collect();
+        // cost[i * count + j] is the cheapest order of the smallest subtree containing both i
+        // and j, starting at i and ending at j
+        let mut cost = vec![0u64; count * count];
+        // Whether each leaf falls under the left child of its subtree, for the merge at hand
+        let mut in_left = vec![false; count];
+        // An order of a subtree that starts at one leaf must end under the other child
+        let ends_with = |node: usize, in_left: &[bool], first: usize, last: usize| {
+            node < count || in_left[first] != in_left[last]
+        };
+
+        for merge in &self.merges {
+            let (l, r) = (merge.left, merge.right);
+            self.mark_left(&leaves, l, &mut in_left);
+            self.mark_left(&leaves, r, &mut in_left);
+            for &i in &leaves[l] {
+                // cheapest[m]: start at i, cover the left subtree, then step to m
+                let cheapest: Vec<u64> = leaves[r]
+                    .iter()
+                    .map(|&m| {
+                        leaves[l]
+                            .iter()
+                            .filter(|&&k| ends_with(l, &in_left, i, k))
+                            .map(|&k| cost[i * count + k] + d(k, m))
+                            .min()
+                            .unwrap()
+                    })
+                    .collect();
+                for &j in &leaves[r] {
+                    let best = leaves[r]
+                        .iter()
+                        .zip(&cheapest)
+                        .filter(|(&m, _)| ends_with(r, &in_left, m, j))
+                        .map(|(&m, &c)| c + cost[m * count + j])
+                        .min()
+                        .unwrap();
+                    cost[i * count + j] = best;
+                    cost[j * count + i] = best;
+                }
+            }
+            let mut merged = leaves[l].clone();
+            merged.extend_from_slice(&leaves[r]);
+            leaves.push(merged);
+        }
+
+        let root = self.merges.last().unwrap();
+        let (mut best_i, mut best_j) = (0, 0);
+        let mut best = u64::MAX;
+        for &i in &leaves[root.left] {
+            for &j in &leaves[root.right] {
+                if cost[i * count + j] < best {
+                    best = cost[i * count + j];
+                    best_i = i;
+                    best_j = j;
+                }
+            }
+        }
+        let mut order = Vec::with_capacity(count);
+        self.emit(
+            2 * count - 2,
+            best_i,
+            best_j,
+            &leaves,
+            &cost,
+            &d,
+            &mut order,
+        );
+        order
+    }
+
+    // Marks which leaves of node fall under its left child
+    fn mark_left(&self, leaves: &[Vec<usize>], node: usize, in_left: &mut [bool]) {
+        if node < self.count {
+            return;
+        }
+        let merge = &self.merges[node - self.count];
+        for &i in &leaves[merge.left] {
+            in_left[i] = true;
+        }
+        for &i in &leaves[merge.right] {
+            in_left[i] = false;
+        }
+    }
+
+    // Leaves of node that an order starting (or ending) at leaf can end (or start) on: those
+    // under the other child
+    fn other_side(&self, leaves: &[Vec<usize>], node: usize, leaf: usize) -> Vec<usize> {
+        if node < self.count {
+            return vec![leaf];
+        }
+        let merge = &self.merges[node - self.count];
+        if leaves[merge.left].contains(&leaf) {
+            leaves[merge.right].clone()
+        } else {
+            leaves[merge.left].clone()
+        }
+    }
+
+    // Appends the leaves of node to order, in its cheapest order from first to last
+    #[allow(clippy::too_many_arguments)]
+    fn emit<F: Fn(usize, usize) -> u64>(
+        &self,
+        node: usize,
+        first: usize,
+        last: usize,
+        leaves: &[Vec<usize>],
+        cost: &[u64],
+        d: &F,
+        order: &mut Vec<usize>,
+    ) {
+        if node < self.count {
+            order.push(node);
+            return;
+        }
+        let count = self.count;
+        let merge = &self.merges[node - count];
+        let (l, r) = if leaves[merge.left].contains(&first) {
+            (merge.left, merge.right)
+        } else {
+            (merge.right, merge.left)
+        };
+        let other_first = self.other_side(leaves, l, first);
+        let other_last = self.other_side(leaves, r, last);
+        let mut best = (u64::MAX, first, last);
+        for &k in &other_first {
+            for &m in &other_last {
+                let total = cost[first * count + k] + d(k, m) + cost[m * count + last];
+                if total < best.0 {
+                    best = (total, k, m);
+                }
+            }
+        }
+        self.emit(l, first, best.1, leaves, cost, d, order);
+        self.emit(r, best.2, last, leaves, cost, d, order);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::distances::TriangularDistances;
+    use crate::hierarchical::*;
+    use crate::testutils::*;
+    use crate::tsp::{Tsp, TspInstance};
+
+    use rand::rngs::StdRng;
+    use rand::seq::SliceRandom;
+    use rand::SeedableRng;
+
+    #[test]
+    fn average_linkage_merges_everything() {
+        let distances = TriangularDistances::from_full(&build_geo_sample(), 7);
+        let dendrogram = Dendrogram::average_linkage(&distances);
+        assert_eq!(6, dendrogram.merges.len());
+        let mut children: Vec<usize> = dendrogram
+            .merges
+            .iter()
+            .flat_map(|m| [m.left, m.right])
+            .collect();
+        children.sort();
+        assert_eq!((0..12).collect::<Vec<usize>>(), children);
+    }
+
+    #[test]
+    fn clusters_follow_group_structure() {
+        // Two groups, distance 1 inside a group and 100 across groups
+        let count = 8;
+        let mut full = vec![0u8; count * count];
+        for i in 0..count {
+            for j in 0..count {
+                if i != j {
+                    full[i * count + j] = if i % 2 == j % 2 { 1 } else { 100 };
+                }
+            }
+        }
+        let distances = TriangularDistances::from_full(&full, count);
+        let dendrogram = Dendrogram::average_linkage(&distances);
+        assert_eq!(vec![0, 1, 0, 1, 0, 1, 0, 1], dendrogram.clusters(2));
+        assert_eq!(vec![0; 8], dendrogram.clusters(1));
+        let singletons = dendrogram.clusters(8);
+        assert_eq!((0..8).collect::<Vec<usize>>(), singletons);
+    }
+
+    #[test]
+    fn optimal_leaf_order_recovers_line() {
+        let count = 12;
+        let mut rng = StdRng::seed_from_u64(33);
+        let mut shuffled: Vec<usize> = (0..count).collect();
+        shuffled.shuffle(&mut rng);
+        let linear = build_linear_distances(count);
+        let mut full = vec![0u8; count * count];
+        for i in 0..count {
+            for j in 0..count {
+                full[i * count + j] = linear[shuffled[i] * count + shuffled[j]];
+            }
+        }
+        let distances = TriangularDistances::from_full(&full, count);
+        let dendrogram = Dendrogram::average_linkage(&distances);
+        let order = dendrogram.optimal_leaf_order(&distances);
+        check_permutation(&order, count - 1);
+        let tsp = Tsp::from_distances(distances);
+        assert_eq!(count as u64 - 1, tsp.calculate_distance(&order));
+    }
+
+    #[test]
+    fn optimal_leaf_order_beats_arbitrary_order() {
+        let distances = TriangularDistances::from_full(&build_random_distances(30), 30);
+        let dendrogram = Dendrogram::average_linkage(&distances);
+        let order = dendrogram.optimal_leaf_order(&distances);
+        check_permutation(&order, 29);
+
+        // The order in which leaves were merged respects the tree, but doesn't optimize it
+        let mut leaves: Vec<Vec<usize>> = (0..30).map(|i| vec![i]).collect();
+        for merge in &dendrogram.merges {
+            let mut merged = leaves[merge.left].clone();
+            merged.extend_from_slice(&leaves[merge.right]);
+            leaves.push(merged);
+        }
+        let tsp = Tsp::from_distances(distances);
+        let tree_order = leaves.last().unwrap();
+        assert!(tsp.calculate_distance(&order) <= tsp.calculate_distance(tree_order));
+    }
+}
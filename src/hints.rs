@@ -0,0 +1,122 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{copy, Read};
+use std::path::{Path, PathBuf};
+
+use crate::lz77::Lz77Estimator;
+
+// Files that don't get at least this much smaller aren't worth the time to compress
+pub const STORE_RATIO: f64 = 0.95;
+// Only the start of each file is estimated. Compressed formats look random all the way through,
+// so a megabyte is plenty to tell.
+const HINT_SAMPLE: u64 = 1 << 20;
+
+/// Whether a file is worth compressing, going by how well its start compresses on its own.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompressionHint {
+    pub path: PathBuf,
+    /// Estimated compressed size over raw size, around 1 for data that doesn't compress
+    pub ratio: f64,
+    pub store: bool,
+}
+
+/**
+ * Estimates how well a file compresses on its own, with the LZ77 estimate of a compressor with
+ * the given window, and recommends storing it if it won't get at least 5% smaller. Already
+ * compressed files, media and archives come out at a ratio of about 1, and so do files too small
+ * to have anything to match. `ByteDistribution::Uniform` catches most of the first, but it only
+ * looks at byte frequencies, which don't say whether a file has repeats.
+ *
+ * Directories have no contents, so they get no hint.
+ */
+pub fn compression_hint(path: &Path, window: usize) -> Result<Option<CompressionHint>, String> {
+    if path.is_dir() {
+        return Ok(None);
+    }
+    let file = File::open(path).map_err(|e| format!("Could not open {}: {}", path.display(), e))?;
+    let mut estimator = Lz77Estimator::new(window)?;
+    let raw_size = copy(&mut file.take(HINT_SAMPLE), &mut estimator)
+        .map_err(|e| format!("Could not read {}: {}", path.display(), e))?;
+    let ratio = if raw_size == 0 {
+        1.0
+    } else {
+        estimator.finish() as f64 / raw_size as f64
+    };
+    Ok(Some(CompressionHint {
+        path: path.to_path_buf(),
+        ratio,
+        store: ratio >= STORE_RATIO,
+    }))
+}
+
+/**
+ * The suffixes of files to store, as zip -n takes them, like ".jpg:.png". zip can only store files
+ * by suffix, so a suffix is listed only when every file with it should be stored. Suffixes are
+ * compared ignoring case, as zip does.
+ */
+pub fn zip_suffixes(hints: &[CompressionHint]) -> String {
+    let mut all_stored: BTreeMap<String, bool> = BTreeMap::new();
+    for hint in hints {
+        if let Some(extension) = hint.path.extension() {
+            let suffix = format!(".{}", extension.to_string_lossy().to_lowercase());
+            *all_stored.entry(suffix).or_insert(true) &= hint.store;
+        }
+    }
+    let suffixes: Vec<String> = all_stored
+        .into_iter()
+        .filter(|(_, stored)| *stored)
+        .map(|(suffix, _)| suffix)
+        .collect();
+    suffixes.join(":")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use rand::rngs::StdRng;
+    use rand::{RngCore, SeedableRng};
+    use tempfile::tempdir;
+
+    use crate::hints::*;
+    use crate::lz77::GZIP_WINDOW;
+
+    #[test]
+    fn random_files_are_stored_and_text_is_compressed() {
+        let tempdir = tempdir().unwrap();
+        let mut random = vec![0; 50000];
+        StdRng::seed_from_u64(48).fill_bytes(&mut random);
+        let random_path = tempdir.path().join("random.bin");
+        fs::write(&random_path, random).unwrap();
+        let text_path = tempdir.path().join("text.txt");
+        fs::write(&text_path, b"to store or not to store ".repeat(2000)).unwrap();
+
+        let random_hint = compression_hint(&random_path, GZIP_WINDOW)
+            .unwrap()
+            .unwrap();
+        assert!(random_hint.store, "{}", random_hint.ratio);
+        let text_hint = compression_hint(&text_path, GZIP_WINDOW).unwrap().unwrap();
+        assert!(!text_hint.store);
+        assert!(text_hint.ratio < 0.1, "{}", text_hint.ratio);
+        assert_eq!(None, compression_hint(tempdir.path(), GZIP_WINDOW).unwrap());
+    }
+
+    #[test]
+    fn zip_suffixes_need_every_file_stored() {
+        let hint = |path: &str, store| CompressionHint {
+            path: PathBuf::from(path),
+            ratio: if store { 1.0 } else { 0.5 },
+            store,
+        };
+        let hints = vec![
+            hint("a.jpg", true),
+            hint("b.JPG", true),
+            hint("c.bin", true),
+            hint("d.bin", false),
+            hint("e.png", true),
+            hint("README", true),
+            hint("f.txt", false),
+        ];
+        assert_eq!(".jpg:.png", zip_suffixes(&hints));
+    }
+}
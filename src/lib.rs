@@ -1,25 +1,68 @@
+pub mod atsp;
+pub mod batching;
 pub mod binsort;
+pub mod blocks;
+pub mod compress;
+pub mod construction;
+pub mod distances;
+pub mod exact;
+pub mod guard;
+pub mod hierarchical;
+pub mod hints;
+pub mod lsh;
+pub mod lz77;
+pub mod magic;
 pub mod minhash;
+pub mod oropt;
+pub mod profile;
+pub mod sigsort;
+pub mod sparse;
+pub mod spectral;
+pub mod stitch;
 pub mod tour;
 pub mod tsp;
+pub mod twolevel;
 pub mod twoopt;
 pub mod utils;
+pub mod window;
 #[cfg(test)]
 mod testutils;
 
+use crate::atsp::{optimize_atsp, AsymmetricTsp};
+use crate::batching::similarity_batches;
 use crate::binsort::*;
+use crate::blocks::partition_blocks;
+use crate::compress::{tar_entry_size, write_tar, CompressorSetting};
+use crate::construction::{farthest_insertion_tour, greedy_edge_tour, savings_tour};
+use crate::distances::TriangularDistances;
+use crate::exact::{solve_cycle, solve_path};
+use crate::guard::{GuardScorer, OrderGuard};
+use crate::hierarchical::Dendrogram;
+use crate::hints::{compression_hint, zip_suffixes};
+use crate::lsh::{lsh_neighbors, LshIndex, DEFAULT_BANDS, DEFAULT_ROWS};
+use crate::lz77::{estimate_compressed_size, parse_size};
+use crate::magic::FileFormat;
 use crate::minhash::*;
-use crate::tour::Tour;
-use crate::tsp::Tsp;
-use crate::twoopt::{optimize_twoopt_from_tour, MINIMUM_ITEMS};
+use crate::profile::Profile;
+use crate::sigsort::{signature_sort, DEFAULT_WINDOW};
+use crate::sparse::{exact_neighbors, SparseTsp};
+use crate::spectral::spectral_order;
+use crate::stitch::{stitch_batches, BatchEnds};
+use crate::tour::{CyclicTour, Tour};
+use crate::tsp::{Tsp, TspInstance, NEIGHBOR_SIZE};
+use crate::twolevel::{TwoLevelTour, TWO_LEVEL_THRESHOLD};
+use crate::twoopt::{optimize_twoopt_from_tour, optimize_twoopt_path, MINIMUM_ITEMS};
+use crate::window::optimize_window;
 
 use pathdiff::diff_paths;
-use std::collections::HashMap;
+use std::cell::OnceCell;
+use std::collections::{BTreeMap, HashMap};
 use std::env::current_dir;
 use std::ffi::OsString;
 use std::path::{Component, Path, PathBuf};
 use walkdir::WalkDir;
 
+use clap::builder::RangedU64ValueParser;
 use clap::{Parser, ValueEnum};
 
 pub fn run(args: Args) -> Result<(), i32> {
@@ -27,8 +70,17 @@ pub fn run(args: Args) -> Result<(), i32> {
     log::info!("Starting to process path {:?}", args.directory);
     match current_dir() {
         Ok(current_dir) => {
-            let ordered_files = load_and_order(args)?;
-            display_files(current_dir, ordered_files)?;
+            let (ordered_files, signatures) = load_and_order(&args)?;
+            if args.blocks > 1 {
+                if let Err(s) = write_blocks(&args, &current_dir, &ordered_files, &signatures) {
+                    log::error!("{}", s);
+                    return Err(exitcode::IOERR);
+                }
+            }
+            match args.output {
+                Output::List => display_files(current_dir, ordered_files)?,
+                _ => display_hints(&args, current_dir, ordered_files)?,
+            }
         }
         Err(s) => {
             log::error!("{}", s);
@@ -39,10 +91,20 @@ pub fn run(args: Args) -> Result<(), i32> {
     Ok(())
 }
 
-fn load_and_order(args: Args) -> Result<Vec<PathBuf>, i32> {
+/// Orders the files under `args.directory` as the simsort binary would, without printing them.
+pub fn order(args: &Args) -> Result<Vec<PathBuf>, String> {
+    let (ordered, _) = process(args, by_filename(Path::new(&args.directory))?)?;
+    Ok(ordered)
+}
+
+// Signatures computed while ordering, by path. They're only kept when the ordering will be split
+// into blocks, so that the files next to each possible cut don't have to be hashed again.
+type Signatures = HashMap<PathBuf, Minhash>;
+
+fn load_and_order(args: &Args) -> Result<(Vec<PathBuf>, Signatures), i32> {
     match by_filename(Path::new(&args.directory)) {
-        Ok(files) => match process(&args, files) {
-            Ok(ordered_files) => Ok(ordered_files),
+        Ok(files) => match process(args, files) {
+            Ok(ordered) => Ok(ordered),
             Err(s) => {
                 log::error!("{}", s);
                 // TODO: Generic exitcode, reconsider later
@@ -72,39 +134,392 @@ fn display_files(current_dir: PathBuf, ordered_files: Vec<PathBuf>) -> Result<()
     Ok(())
 }
 
+// Prints, for each file in order, whether to store or compress it, in the --output format
+fn display_hints(
+    args: &Args,
+    current_dir: PathBuf,
+    ordered_files: Vec<PathBuf>,
+) -> Result<(), i32> {
+    let mut hints = Vec::new();
+    for f in &ordered_files {
+        match compression_hint(f, args.window()) {
+            Ok(Some(hint)) => hints.push(hint),
+            Ok(None) => {}
+            Err(s) => {
+                log::error!("{}", s);
+                return Err(exitcode::IOERR);
+            }
+        }
+    }
+    if args.output == Output::ZipSuffixes {
+        println!("{}", zip_suffixes(&hints));
+        return Ok(());
+    }
+    for hint in hints {
+        if args.output == Output::StoreList && !hint.store {
+            continue;
+        }
+        let p = match output_path(&hint.path, &current_dir) {
+            Ok(p) => p,
+            Err(s) => {
+                log::error!("{}", s);
+                // TODO: Generic exitcode, reconsider later
+                return Err(1);
+            }
+        };
+        match args.output {
+            Output::StoreList => println!("{}", p.display()),
+            _ => {
+                let recommendation = if hint.store { "store" } else { "compress" };
+                println!("{}\t{:.3}\t{}", recommendation, hint.ratio, p.display());
+            }
+        }
+    }
+    Ok(())
+}
+
 #[derive(Debug, Clone, Copy, ValueEnum)]
 enum Algorithm {
     Tsp,
     OnlyExtensions,
     ByteDistributions,
     BinsortOriginal,
+    AsymmetricTsp,
+    SparseTsp,
+    Hierarchical,
+    Spectral,
+    SignatureSort,
+    /// Try several of the algorithms above on each batch, and keep the best ordering
+    Auto,
 }
 
 impl Algorithm {
+    // Whether the algorithm orders files by the distances between them, which --entropy-weight
+    // adds to. Signature sort compares neighboring files within its window by minhash alone.
+    fn compares_distances(&self) -> bool {
+        !matches!(
+            self,
+            Self::OnlyExtensions | Self::ByteDistributions | Self::SignatureSort
+        )
+    }
+
+    // Whether an ordering costs the same read backwards, so that batches can be reversed to join
+    // them. With asymmetric distances, placing one file after another isn't the same as before it.
+    fn reversible(&self) -> bool {
+        !matches!(self, Self::AsymmetricTsp)
+    }
+
     fn max_batch(&self) -> usize {
         match self {
-            Self::Tsp => 10000,
+            // Packed distances for 14000 files take about the same memory as the full matrix for
+            // 10000
+            Self::Tsp => 14000,
             Self::ByteDistributions => usize::MAX,
             Self::OnlyExtensions => usize::MAX,
             Self::BinsortOriginal => 10000,
+            Self::AsymmetricTsp => 10000,
+            // Memory is linear in the batch size, time is the limit here
+            Self::SparseTsp => 500000,
+            // Optimal leaf ordering is cubic in the batch size
+            Self::Hierarchical => 1000,
+            Self::Spectral => 14000,
+            Self::SignatureSort => usize::MAX,
+            // Orderings are scored against the full distance matrix, like tsp
+            Self::Auto => 14000,
         }
     }
 
-    fn order<'a>(&self, files: Vec<MinhashTarget>) -> Result<Vec<PathBuf>, String> {
+    fn order(&self, args: &Args, files: Vec<MinhashTarget>) -> Result<Vec<PathBuf>, String> {
         match self {
-            Self::Tsp => order_tsp(files),
+            Self::Tsp => order_tsp(args, files),
             Self::ByteDistributions => convert_to_pathbufs(files),
             Self::OnlyExtensions => convert_to_pathbufs(files),
-            Self::BinsortOriginal => Ok(order_binsort(files)),
+            Self::BinsortOriginal => Ok(order_binsort(args, files)),
+            Self::AsymmetricTsp => order_atsp(args, files),
+            Self::SparseTsp => order_sparse_tsp(args, files),
+            Self::Hierarchical => Ok(order_hierarchical(args, files)),
+            Self::Spectral => Ok(order_spectral(args, files)),
+            Self::SignatureSort => order_signature_sort(args, files),
+            Self::Auto => order_auto(args, files),
+        }
+    }
+}
+
+/// How auto scores the orderings it tries
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum AutoScore {
+    /// Total distance between neighboring files
+    Length,
+    /// Estimated compressed size, with the --window of the compressor
+    Estimate,
+}
+
+/// What the tsp ordering minimizes. Archives are read front to back, so by default we optimize an
+/// open path and never pay for an edge from the last file back to the first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Objective {
+    Path,
+    Cycle,
+    /// Optimize a path, then refine it so each file is close to any similar file within the
+    /// compressor's --window, not just the file before it
+    Window,
+}
+
+/// How the tsp algorithms build the tour that local search starts from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Construction {
+    /// The order files were found in
+    Walk,
+    NearestNeighbor,
+    GreedyEdge,
+    Savings,
+    /// Compares every pair of files, so batches of more than 20000 files use greedy-edge instead,
+    /// with a warning
+    FarthestInsertion,
+    /// Build every tour above, and keep the shortest
+    Best,
+}
+
+// Farthest insertion compares every pair of files, so on the larger batches sparse-tsp allows,
+// where each distance is recomputed from signatures, it wouldn't finish
+const FARTHEST_INSERTION_LIMIT: usize = 20000;
+
+impl Construction {
+    const CANDIDATES: [Construction; 5] = [
+        Self::Walk,
+        Self::NearestNeighbor,
+        Self::GreedyEdge,
+        Self::Savings,
+        Self::FarthestInsertion,
+    ];
+
+    fn build<I: TspInstance>(&self, tsp: &I) -> Vec<usize> {
+        match self {
+            Self::Walk => (0..tsp.count()).collect(),
+            Self::NearestNeighbor => tsp.generate_nearest_neighbor_tour(0),
+            Self::GreedyEdge => greedy_edge_tour(tsp),
+            Self::Savings => savings_tour(tsp),
+            Self::FarthestInsertion if tsp.count() > FARTHEST_INSERTION_LIMIT => {
+                log::warn!(
+                    "Using greedy edge instead of farthest insertion for {} files, which is \
+                     more than farthest insertion handles ({})",
+                    tsp.count(),
+                    FARTHEST_INSERTION_LIMIT
+                );
+                greedy_edge_tour(tsp)
+            }
+            Self::FarthestInsertion => farthest_insertion_tour(tsp),
+            Self::Best => {
+                let cycle_length = |order: &[usize]| {
+                    tsp.calculate_distance(order)
+                        + tsp.retrieve_distance(order[order.len() - 1], order[0])
+                };
+                let (construction, order, distance) = Self::CANDIDATES
+                    .iter()
+                    .filter(|c| {
+                        **c != Self::FarthestInsertion || tsp.count() <= FARTHEST_INSERTION_LIMIT
+                    })
+                    .map(|c| {
+                        let order = c.build(tsp);
+                        let distance = cycle_length(&order);
+                        (*c, order, distance)
+                    })
+                    .min_by_key(|(_, _, distance)| *distance)
+                    .unwrap();
+                log::info!(
+                    "Best construction is {:?}, distance={}",
+                    construction,
+                    distance
+                );
+                order
+            }
         }
     }
 }
 
+/// Whether to check the ordering against the order files were found in, and how
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Guard {
+    Off,
+    /// Score both orders with the built-in LZ77 estimate
+    Estimate,
+    /// Score both orders by running the compressor on a sample
+    Trial,
+}
+
+/// How the blocks of --blocks are written out, next to the full ordering on standard output
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum BlockFormat {
+    /// A list of the files in each block, in <prefix>-<n>.txt
+    Lists,
+    /// A tar archive of each block, in <prefix>-<n>.tar
+    Tars,
+    /// Where the blocks start in a tar of the full ordering, as a comma separated list of block
+    /// sizes in <prefix>.sizes, which is what xz --block-list takes
+    Sizes,
+}
+
+/// What simsort prints
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Output {
+    /// The ordered files, one per line
+    List,
+    /// For each file, store or compress, its estimated compression ratio and its path, tab
+    /// separated
+    Hints,
+    /// The suffixes of files to store, for zip -n
+    ZipSuffixes,
+    /// Just the files to store, in order, like for 7z -m0=Copy @list
+    StoreList,
+}
+
+/// Whether the guard decides once, for the whole archive, or for each batch of similar files
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum GuardScope {
+    Global,
+    Batch,
+}
+
 #[derive(Parser, Debug)]
 pub struct Args {
     directory: String,
     #[arg(value_enum)]
     algorithm: Algorithm,
+    /// The compressor the archive is for, which sets the defaults of the options that tune
+    /// simsort to it
+    #[arg(long, value_enum, default_value_t = Profile::Gzip)]
+    compressor: Profile,
+    /// Defaults to window if the compressor can match beyond the previous file, path otherwise
+    #[arg(long, value_enum)]
+    objective: Option<Objective>,
+    /// File to place first in the ordering
+    #[arg(long)]
+    first: Option<PathBuf>,
+    /// File to place last in the ordering
+    #[arg(long)]
+    last: Option<PathBuf>,
+    /// Number of bands in the LSH index used to find neighbors for sparse-tsp
+    #[arg(
+        long,
+        default_value_t = DEFAULT_BANDS,
+        value_parser = RangedU64ValueParser::<usize>::new().range(1..)
+    )]
+    lsh_bands: usize,
+    /// Number of rows per band in the LSH index, more rows only match more similar files
+    #[arg(
+        long,
+        default_value_t = DEFAULT_ROWS,
+        value_parser = RangedU64ValueParser::<usize>::new().range(1..)
+    )]
+    lsh_rows: usize,
+    /// How to build the starting tour for tsp local search
+    #[arg(long, value_enum, default_value_t = Construction::Walk)]
+    construction: Construction,
+    /// Start tsp local search from the spectral ordering, instead of using --construction
+    #[arg(long)]
+    spectral_seed: bool,
+    /// Number of files signature-sort optimizes together after sorting
+    #[arg(long, default_value_t = DEFAULT_WINDOW)]
+    signature_window: usize,
+    /// Fall back to the order files were found in, if the ordering doesn't compress better.
+    /// A bare --guard uses the estimate.
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = Guard::Off,
+        num_args = 0..=1,
+        default_missing_value = "estimate"
+    )]
+    guard: Guard,
+    /// Whether the guard compares the whole ordering or each batch
+    #[arg(long, value_enum, default_value_t = GuardScope::Global)]
+    guard_scope: GuardScope,
+    /// Compressor and level for --guard trial, like gzip:6 or zstd:19. Defaults to the
+    /// --compressor at its default level.
+    #[arg(long)]
+    guard_compressor: Option<CompressorSetting>,
+    /// How many bytes of each ordering the guard compresses, at most
+    #[arg(long, default_value = "64M", value_parser = parse_size)]
+    guard_sample: usize,
+    /// Window of the compressor that compressed size estimates simulate, like 32K for gzip or
+    /// 64M for xz. Defaults to the window of the --compressor.
+    #[arg(long, value_parser = parse_size)]
+    window: Option<usize>,
+    /// Length of the byte strings compared between files. Defaults to what suits the --compressor.
+    #[arg(long, value_parser = clap::value_parser!(u8).range(1..))]
+    shingle_size: Option<u8>,
+    /// Number of hashes kept to compare each file. Defaults to what suits the --compressor.
+    #[arg(long, value_parser = clap::value_parser!(u8).range(1..))]
+    feature_count: Option<u8>,
+    /// How auto compares the algorithms it tries on each batch
+    #[arg(long, value_enum, default_value_t = AutoScore::Length)]
+    auto_score: AutoScore,
+    /// Seconds auto may spend on each batch. Once they're used up, no more algorithms are
+    /// started, though one that's running is allowed to finish.
+    #[arg(long, default_value_t = 60.0)]
+    auto_budget: f64,
+    /// How much a difference in entropy between two files adds to the distance between them, from
+    /// 0 for none to 1 for as much as minhash similarity. Not for only-extensions,
+    /// byte-distributions or signature-sort, which don't compare distances.
+    #[arg(long, default_value_t = 0.0)]
+    entropy_weight: f64,
+    /// Whether to print the ordering, or hints on which files are worth compressing
+    #[arg(long, value_enum, default_value_t = Output::List)]
+    output: Output,
+    /// Split the ordering into this many blocks of about the same size, for compressors that
+    /// compress blocks independently, like xz -T, zstd -T or 7z solid blocks
+    #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u32).range(1..))]
+    blocks: u32,
+    /// How to write out the blocks
+    #[arg(long, value_enum, default_value_t = BlockFormat::Lists)]
+    block_format: BlockFormat,
+    /// Start of the names of the files the blocks are written to
+    #[arg(long, default_value = "block")]
+    block_prefix: PathBuf,
+}
+
+// Options that default to a setting of the compressor profile
+impl Args {
+    fn objective(&self) -> Objective {
+        match self.objective {
+            Some(objective) => objective,
+            None if self.compressor.settings().window_objective => Objective::Window,
+            None => Objective::Path,
+        }
+    }
+
+    fn window(&self) -> usize {
+        self.window.unwrap_or(self.compressor.settings().window)
+    }
+
+    // The profile's batch limit only applies where batches are optimized for the window
+    fn max_batch(&self) -> usize {
+        match (&self.algorithm, self.objective()) {
+            (Algorithm::Tsp | Algorithm::SparseTsp, Objective::Window) => self
+                .algorithm
+                .max_batch()
+                .min(self.compressor.settings().max_batch),
+            _ => self.algorithm.max_batch(),
+        }
+    }
+
+    fn minhash_settings(&self) -> MinhashSettings {
+        let defaults = self.compressor.settings().minhash;
+        MinhashSettings {
+            shingle_size: self.shingle_size.unwrap_or(defaults.shingle_size),
+            feature_count: self.feature_count.unwrap_or(defaults.feature_count),
+        }
+    }
+
+    fn guard_compressor(&self) -> Result<CompressorSetting, String> {
+        match self.guard_compressor.or(self.compressor.settings().trial) {
+            Some(setting) => Ok(setting),
+            None => Err(format!(
+                "simsort can't run {:?} for --guard trial, pick one with --guard-compressor",
+                self.compressor
+            )),
+        }
+    }
 }
 
 fn output_path(file_path: &PathBuf, current_dir: &PathBuf) -> Result<PathBuf, String> {
@@ -141,62 +556,290 @@ fn output_path(file_path: &PathBuf, current_dir: &PathBuf) -> Result<PathBuf, St
 fn process(
     args: &Args,
     files: HashMap<OsString, Vec<MinhashTarget>>,
-) -> Result<Vec<PathBuf>, String> {
+) -> Result<(Vec<PathBuf>, Signatures), String> {
     let size: usize = files.values().map(|v| v.len()).sum();
     log::info!("Processing {:?} files", size);
-    match args.algorithm {
-        Algorithm::OnlyExtensions => {
-            let ordered: Vec<PathBuf> = files
+    if args.first.is_some() && args.first == args.last {
+        return Err("The same file cannot be placed both first and last".to_string());
+    }
+    if !(0.0..=1.0).contains(&args.entropy_weight) {
+        return Err(format!(
+            "The entropy weight must be from 0 to 1, not {}",
+            args.entropy_weight
+        ));
+    }
+    if args.entropy_weight > 0.0 && !args.algorithm.compares_distances() {
+        let name = args
+            .algorithm
+            .to_possible_value()
+            .unwrap()
+            .get_name()
+            .to_string();
+        return Err(format!(
+            "--entropy-weight has no effect on {}, which does not compare distances",
+            name
+        ));
+    }
+    let guard = build_guard(args)?;
+    let (ordered, signatures) = match args.algorithm {
+        Algorithm::OnlyExtensions => (
+            files
                 .into_values()
                 .flatten()
                 .map(|t| t.get_path().to_path_buf())
+                .collect(),
+            Signatures::new(),
+        ),
+        _ => order_in_batches(args, files, guard.as_ref())?,
+    };
+    let ordered = match (&guard, args.guard_scope) {
+        (Some(guard), GuardScope::Global) => guard.choose(ordered, "the archive")?,
+        _ => ordered,
+    };
+    Ok((pin_ends(args, ordered), signatures))
+}
+
+fn build_guard(args: &Args) -> Result<Option<OrderGuard>, String> {
+    let scorer = match args.guard {
+        Guard::Off => return Ok(None),
+        Guard::Estimate => GuardScorer::Estimate(args.window()),
+        Guard::Trial => GuardScorer::Trial(args.guard_compressor()?),
+    };
+    // The natural order is the order the directory walk finds files in, as tar would
+    let mut walk = Vec::new();
+    for entry in WalkDir::new(&args.directory) {
+        match entry {
+            Ok(e) => walk.push(e.path().to_path_buf()),
+            Err(e) => return Err(format!("Failure to read file, {}", e)),
+        }
+    }
+    Ok(Some(OrderGuard::new(
+        scorer,
+        args.guard_sample as u64,
+        &walk,
+    )))
+}
+
+/**
+ * Splits the ordering into --blocks blocks of about the same size, cutting between dissimilar
+ * files, and writes each block out in the --block-format. Sizes are what the files take up in a
+ * tar archive, so that the blocks line up with the archive.
+ */
+fn write_blocks(
+    args: &Args,
+    current_dir: &PathBuf,
+    ordered: &[PathBuf],
+    signatures: &Signatures,
+) -> Result<(), String> {
+    // Sizes in the archive tar makes from the list on standard output
+    let sizes = ordered
+        .iter()
+        .map(|path| Ok(tar_entry_size(path, &output_path(path, current_dir)?)))
+        .collect::<Result<Vec<u64>, String>>()?;
+    let settings = args.minhash_settings();
+    // Files that weren't hashed while ordering, like any file for only-extensions, are hashed
+    // here, but only if they're next to a possible cut
+    let mut unordered: HashMap<usize, Option<Minhash>> = HashMap::new();
+    let blocks = partition_blocks(&sizes, args.blocks as usize, |p| {
+        for i in [p - 1, p] {
+            if !signatures.contains_key(&ordered[i]) {
+                unordered
+                    .entry(i)
+                    .or_insert_with(|| block_signature(&ordered[i], settings));
+            }
+        }
+        let signature = |i: usize| {
+            signatures
+                .get(&ordered[i])
+                .or_else(|| unordered[&i].as_ref())
+                .filter(|minhash| !minhash.features().is_empty())
+        };
+        match (signature(p - 1), signature(p)) {
+            (Some(before), Some(after)) => similarity_to_distance(before.score(after)) as u64,
+            _ => u8::MAX as u64,
+        }
+    });
+    log::info!("Split {} files into {} blocks", ordered.len(), blocks.len());
+
+    let digits = blocks.len().to_string().len();
+    let block_path = |number: usize, extension: &str| {
+        let mut name = args.block_prefix.clone().into_os_string();
+        name.push(format!("-{:0digits$}.{}", number, extension));
+        PathBuf::from(name)
+    };
+    match args.block_format {
+        BlockFormat::Lists => {
+            for (number, block) in blocks.iter().enumerate() {
+                let mut list = String::new();
+                for path in &ordered[block.clone()] {
+                    list.push_str(&format!("{}\n", output_path(path, current_dir)?.display()));
+                }
+                let destination = block_path(number, "txt");
+                std::fs::write(&destination, list)
+                    .map_err(|e| format!("Could not write {}: {}", destination.display(), e))?;
+            }
+        }
+        BlockFormat::Tars => {
+            for (number, block) in blocks.iter().enumerate() {
+                write_tar(&ordered[block.clone()], &block_path(number, "tar"))?;
+            }
+        }
+        BlockFormat::Sizes => {
+            let block_sizes: Vec<String> = blocks
+                .iter()
+                .map(|block| sizes[block.clone()].iter().sum::<u64>().to_string())
                 .collect();
-            return Ok(ordered);
+            let mut destination = args.block_prefix.clone().into_os_string();
+            destination.push(".sizes");
+            std::fs::write(&destination, block_sizes.join(",") + "\n")
+                .map_err(|e| format!("Could not write {:?}: {}", destination, e))?;
         }
-        _ => {
-            return Ok(order_in_batches(&args.algorithm, files)?);
+    }
+    Ok(())
+}
+
+// The signature of a file next to a block boundary that wasn't hashed while ordering
+fn block_signature(path: &Path, settings: MinhashSettings) -> Option<Minhash> {
+    let target = if path.is_dir() {
+        MinhashTarget::Directory(path.to_path_buf())
+    } else {
+        MinhashTarget::File(path.to_path_buf())
+    };
+    minhash_stream_with(&target, settings).ok()
+}
+
+// Applies the guard to one batch, if the guard decides per batch
+fn guard_batch(
+    args: &Args,
+    guard: Option<&OrderGuard>,
+    ordered: Vec<PathBuf>,
+    label: &str,
+) -> Result<Vec<PathBuf>, String> {
+    match guard {
+        Some(guard) if args.guard_scope == GuardScope::Batch => guard.choose(ordered, label),
+        _ => Ok(ordered),
+    }
+}
+
+// Batches are optimized with the pinned files at their ends, but the batches themselves are
+// concatenated in a fixed order, so we still have to move the pinned files to the ends of the
+// output.
+fn pin_ends(args: &Args, mut ordered: Vec<PathBuf>) -> Vec<PathBuf> {
+    if let Some(first) = &args.first {
+        match ordered.iter().position(|p| same_file(p, first)) {
+            Some(i) => {
+                let path = ordered.remove(i);
+                ordered.insert(0, path);
+            }
+            None => log::warn!("File to place first, {:?}, was not found", first),
         }
     }
+    if let Some(last) = &args.last {
+        match ordered.iter().position(|p| same_file(p, last)) {
+            Some(i) => {
+                let path = ordered.remove(i);
+                ordered.push(path);
+            }
+            None => log::warn!("File to place last, {:?}, was not found", last),
+        }
+    }
+    ordered
+}
+
+fn same_file(candidate: &Path, target: &Path) -> bool {
+    if candidate == target {
+        return true;
+    }
+    // Only canonicalize when the names match, since it requires hitting the filesystem
+    if candidate.file_name() != target.file_name() {
+        return false;
+    }
+    match (candidate.canonicalize(), target.canonicalize()) {
+        (Ok(c), Ok(t)) => c == t,
+        _ => false,
+    }
+}
+
+fn pinned_index(pinned: &Option<PathBuf>, files: &[PathBuf]) -> Option<usize> {
+    pinned
+        .as_ref()
+        .and_then(|p| files.iter().position(|f| same_file(f, p)))
+}
+
+// Binaries with entropy, in bits per byte, below each of these go in a separate class, so that
+// sparse tables aren't ordered together with dense machine code just because neither is ascii
+const ENTROPY_BANDS: [f64; 2] = [4.0, 7.0];
+
+// Binaries with at least this fraction of printable bytes are mostly text, in utf-8 or another
+// encoding that extends ascii, and go in a class of their own whatever their entropy
+const MOSTLY_PRINTABLE: f64 = 0.9;
+
+fn binary_class(statistics: &ByteStatistics) -> usize {
+    if statistics.printable >= MOSTLY_PRINTABLE {
+        return ENTROPY_BANDS.len() + 1;
+    }
+    ENTROPY_BANDS
+        .iter()
+        .filter(|&&bound| statistics.entropy >= bound)
+        .count()
 }
 
 fn order_in_batches(
-    algorithm: &Algorithm,
+    args: &Args,
     files: HashMap<OsString, Vec<MinhashTarget>>,
-) -> Result<Vec<PathBuf>, String> {
+    guard: Option<&OrderGuard>,
+) -> Result<(Vec<PathBuf>, Signatures), String> {
     // TODO: may eventually be worth making max batch based on a command line switch--idea being you can choose efficiency or performance
+    let settings = args.minhash_settings();
     let mut uniform_pending = Vec::new();
     let mut ascii_pending = Vec::new();
-    let mut ascii_processed = Vec::new();
-    let mut remainder_pending = Vec::new();
-    let mut remainder_processed = Vec::new();
+    let mut ascii_signatures = Vec::new();
+    // Other binaries are grouped by entropy band, or as mostly text, as well
+    let mut remainder_pending: BTreeMap<usize, (Vec<MinhashTarget>, Vec<Minhash>)> =
+        BTreeMap::new();
+    // Files with a magic number we know are grouped by format, whatever their bytes look like
+    let mut compressed_pending: BTreeMap<FileFormat, Vec<PathBuf>> = BTreeMap::new();
+    let mut formatted_pending: BTreeMap<FileFormat, (Vec<MinhashTarget>, Vec<Minhash>)> =
+        BTreeMap::new();
     let mut unhashed = Vec::new();
+    let keep_signatures = args.blocks > 1;
+    let mut kept = Signatures::new();
     for next_files in files.into_values() {
         for target in next_files {
-            match minhash_stream(&target) {
-                Ok(minhash) => {
-                    match minhash.byte_distribution {
-                        // TODO: do proper bin-packing here
+            match minhash_stream_with(&target, settings) {
+                Ok(minhash) => match minhash.format {
+                    Some(format) if format.is_compressed() => {
+                        let path = target.get_path().to_path_buf();
+                        if keep_signatures {
+                            kept.insert(path.clone(), minhash);
+                        }
+                        compressed_pending.entry(format).or_default().push(path);
+                    }
+                    Some(format) => {
+                        let (targets, signatures) = formatted_pending.entry(format).or_default();
+                        targets.push(target);
+                        signatures.push(minhash);
+                    }
+                    None => match minhash.byte_distribution {
                         ByteDistribution::Uniform => {
-                            uniform_pending.push(target.get_path().to_path_buf());
+                            let path = target.get_path().to_path_buf();
+                            if keep_signatures {
+                                kept.insert(path.clone(), minhash);
+                            }
+                            uniform_pending.push(path);
                         }
                         ByteDistribution::Ascii(_) => {
                             ascii_pending.push(target);
-                            if ascii_pending.len() > algorithm.max_batch() {
-                                let mut foo = algorithm.order(ascii_pending)?;
-                                ascii_processed.append(&mut foo);
-                                ascii_pending = Vec::new();
-                            }
+                            ascii_signatures.push(minhash);
                         }
                         ByteDistribution::NonAscii(_) => {
-                            remainder_pending.push(target);
-                            if remainder_pending.len() > algorithm.max_batch() {
-                                let mut foo = algorithm.order(remainder_pending)?;
-                                remainder_processed.append(&mut foo);
-                                remainder_pending = Vec::new();
-                            }
+                            let class = binary_class(&minhash.statistics);
+                            let (targets, signatures) = remainder_pending.entry(class).or_default();
+                            targets.push(target);
+                            signatures.push(minhash);
                         }
-                    }
-                }
+                    },
+                },
                 Err(error_string) => {
                     log::error!("Failed to read target={:?}, {}", target.get_path(), error_string);
                     // TODO: reconsider error handling logic
@@ -208,27 +851,142 @@ fn order_in_batches(
         }
     }
 
-    let ascii_count = ascii_processed.len() + ascii_pending.len();
-    let remainder_count = remainder_processed.len() + remainder_pending.len();
-    log::debug!("{} ascii files, {} non-ascii files, {} uniform files, {} unhashed files", ascii_count, remainder_count, uniform_pending.len(), unhashed.len());
+    log::debug!(
+        "{} ascii files, {} non-ascii files, {} uniform files, {} unhashed files",
+        ascii_pending.len(),
+        remainder_pending
+            .values()
+            .map(|(targets, _)| targets.len())
+            .sum::<usize>(),
+        uniform_pending.len(),
+        unhashed.len()
+    );
     
-    let mut ordered = uniform_pending;
-    ordered.append(&mut remainder_processed);
-    ordered.append(&mut algorithm.order(remainder_pending)?);
-    ordered.append(&mut ascii_processed);
-    ordered.append(&mut algorithm.order(ascii_pending)?);
+    log::debug!(
+        "{} compressed files, {} files in other known formats",
+        compressed_pending
+            .values()
+            .map(|paths| paths.len())
+            .sum::<usize>(),
+        formatted_pending
+            .values()
+            .map(|(targets, _)| targets.len())
+            .sum::<usize>()
+    );
+
+    // Already compressed files won't compress any further, so they go with the uniform files,
+    // with files of the same format together since they share at least their headers
+    let mut incompressible: Vec<PathBuf> = compressed_pending.into_values().flatten().collect();
+    incompressible.append(&mut uniform_pending);
+    let mut ordered = Vec::new();
+    let uniform_last = args.compressor.settings().uniform_last;
+    if !uniform_last {
+        ordered.append(&mut incompressible);
+    }
+    let classes = remainder_pending
+        .into_values()
+        .chain(formatted_pending.into_values())
+        .chain([(ascii_pending, ascii_signatures)]);
+    for (targets, signatures) in classes {
+        let paths: Vec<PathBuf> = if keep_signatures {
+            targets.iter().map(|t| t.get_path().to_path_buf()).collect()
+        } else {
+            Vec::new()
+        };
+        ordered.append(&mut order_class(args, targets, &signatures, guard)?);
+        kept.extend(paths.into_iter().zip(signatures));
+    }
+    ordered.append(&mut incompressible);
     ordered.append(&mut unhashed);
-    Ok(ordered)
+    Ok((ordered, kept))
+}
+
+// Orders one class of files. If it's too big for the algorithm to handle at once, we split it
+// into batches of similar files, order each batch, then join the batches at their most similar
+// ends.
+fn order_class(
+    args: &Args,
+    targets: Vec<MinhashTarget>,
+    signatures: &[Minhash],
+    guard: Option<&OrderGuard>,
+) -> Result<Vec<PathBuf>, String> {
+    let algorithm = &args.algorithm;
+    if targets.len() <= args.max_batch() {
+        let label = format!("a batch of {} files", targets.len());
+        return guard_batch(args, guard, algorithm.order(args, targets)?, &label);
+    }
+    let batches = similarity_batches(signatures, args.max_batch());
+    log::info!(
+        "Split {} files into {} batches",
+        targets.len(),
+        batches.len()
+    );
+    let signature_index: HashMap<PathBuf, usize> = targets
+        .iter()
+        .enumerate()
+        .map(|(i, t)| (t.get_path().to_path_buf(), i))
+        .collect();
+    let mut targets: Vec<Option<MinhashTarget>> = targets.into_iter().map(Some).collect();
+    let mut ordered_batches = Vec::new();
+    for (number, batch) in batches.iter().enumerate() {
+        let batch_targets = batch.iter().map(|&i| targets[i].take().unwrap()).collect();
+        let label = format!("batch {} of {} files", number, batch.len());
+        let ordered = algorithm.order(args, batch_targets)?;
+        ordered_batches.push(guard_batch(args, guard, ordered, &label)?);
+    }
+
+    Ok(join_batches(
+        algorithm,
+        ordered_batches,
+        &signature_index,
+        signatures,
+    ))
 }
 
-pub fn compute_distances(targets: Vec<MinhashTarget>) -> (Vec<u8>, Vec<PathBuf>, Vec<PathBuf>) {
+// Joins ordered batches at their most similar ends. Batches are only reversed if the algorithm
+// ordered them the same in either direction.
+fn join_batches(
+    algorithm: &Algorithm,
+    ordered_batches: Vec<Vec<PathBuf>>,
+    signature_index: &HashMap<PathBuf, usize>,
+    signatures: &[Minhash],
+) -> Vec<PathBuf> {
+    let end_signature = |path: Option<&PathBuf>| {
+        path.and_then(|p| signature_index.get(p))
+            .map(|&i| &signatures[i])
+    };
+    let ends: Vec<BatchEnds> = ordered_batches
+        .iter()
+        .map(|batch| BatchEnds {
+            first: end_signature(batch.first()),
+            last: end_signature(batch.last()),
+        })
+        .collect();
+    let stitched = stitch_batches(&ends, algorithm.reversible());
+    let mut ordered_batches: Vec<Option<Vec<PathBuf>>> =
+        ordered_batches.into_iter().map(Some).collect();
+    let mut ordered = Vec::new();
+    for (batch, reversed) in stitched {
+        let mut paths = ordered_batches[batch].take().unwrap();
+        if reversed {
+            paths.reverse();
+        }
+        ordered.append(&mut paths);
+    }
+    ordered
+}
+
+fn hash_targets(
+    targets: Vec<MinhashTarget>,
+    settings: MinhashSettings,
+) -> (Vec<Minhash>, Vec<PathBuf>, Vec<PathBuf>) {
     let mut simhashes = vec![];
     let mut hashed_files = vec![];
     let mut unhashed_files = vec![];
     utils::perf_trace("Creating simhashes", "Minhash", "B", utils::get_micros());
     for target in targets {
         let start = utils::get_micros();
-        let result = minhash_stream(&target);
+        let result = minhash_stream_with(&target, settings);
         match result {
             Ok(hash) => {
                 utils::perf_trace("Minhash", "Minhash", "X", start);
@@ -243,21 +1001,60 @@ pub fn compute_distances(targets: Vec<MinhashTarget>) -> (Vec<u8>, Vec<PathBuf>,
     }
     utils::perf_trace("Creating simhashes", "Minhash", "E", utils::get_micros());
     log::info!("Created simhashes for {} files", hashed_files.len());
-    let file_count = hashed_files.len();
-    let mut distances = vec![0; file_count * file_count];
+    (simhashes, hashed_files, unhashed_files)
+}
+
+pub fn compute_distances(
+    targets: Vec<MinhashTarget>,
+    settings: MinhashSettings,
+    entropy_weight: f64,
+) -> (TriangularDistances<u8>, Vec<PathBuf>, Vec<PathBuf>) {
+    let (simhashes, hashed_files, unhashed_files) = hash_targets(targets, settings);
+    let distances = signature_distances(&simhashes, entropy_weight);
+    (distances, hashed_files, unhashed_files)
+}
+
+// The distance between every pair of signatures, for files that have already been hashed
+fn signature_distances(simhashes: &[Minhash], entropy_weight: f64) -> TriangularDistances<u8> {
+    let mut distances = TriangularDistances::blocked(simhashes.len());
     utils::perf_trace("Creating distances", "Distances", "B", utils::get_micros());
-    for i in 0..hashed_files.len() {
+    for i in 0..simhashes.len() {
         utils::perf_trace("Distances for file", "Distances", "B", utils::get_micros());
-        for j in i..hashed_files.len() {
+        for j in i..simhashes.len() {
             let similarity = simhashes[i].score(&simhashes[j]);
-            let distance = 255 - ((similarity * 255.0).floor() as u8);
-            distances[i * file_count + j] = distance;
-            distances[j * file_count + i] = distance;
+            let distance = similarity_to_distance(similarity);
+            let distance = add_entropy_term(distance, &simhashes[i], &simhashes[j], entropy_weight);
+            distances.set(i, j, distance);
         }
         utils::perf_trace("Distances for file", "Distances", "E", utils::get_micros());
     }
     utils::perf_trace("Creating distances", "Distances", "E", utils::get_micros());
-    return (distances, hashed_files, unhashed_files);
+    distances
+}
+
+/// Like compute_distances, but the distance from i to j is the cost of placing j directly after i,
+/// which is low when j is mostly contained in i. The matrix is row-major, with rows being the
+/// earlier file.
+pub fn compute_asymmetric_distances(
+    targets: Vec<MinhashTarget>,
+    settings: MinhashSettings,
+    entropy_weight: f64,
+) -> (Vec<u8>, Vec<PathBuf>, Vec<PathBuf>) {
+    let (simhashes, hashed_files, unhashed_files) = hash_targets(targets, settings);
+    let file_count = hashed_files.len();
+    let mut distances = vec![0; file_count * file_count];
+    utils::perf_trace("Creating distances", "Distances", "B", utils::get_micros());
+    for i in 0..file_count {
+        for j in 0..file_count {
+            if i != j {
+                let distance = similarity_to_distance(simhashes[j].containment(&simhashes[i]));
+                distances[i * file_count + j] =
+                    add_entropy_term(distance, &simhashes[i], &simhashes[j], entropy_weight);
+            }
+        }
+    }
+    utils::perf_trace("Creating distances", "Distances", "E", utils::get_micros());
+    (distances, hashed_files, unhashed_files)
 }
 
 fn convert_to_pathbufs(files: Vec<MinhashTarget>) -> Result<Vec<PathBuf>, String> {
@@ -267,29 +1064,306 @@ fn convert_to_pathbufs(files: Vec<MinhashTarget>) -> Result<Vec<PathBuf>, String
         .collect())
 }
 
-fn order_tsp(files: Vec<MinhashTarget>) -> Result<Vec<PathBuf>, String> {
+fn order_tsp(args: &Args, files: Vec<MinhashTarget>) -> Result<Vec<PathBuf>, String> {
     let mut paths = vec![];
 
     // TODO: we're recomputing the hashes here, which is a waste
-    let (distances, hashed_files, mut unhashed_files) = compute_distances(files);
+    let settings = args.minhash_settings();
+    let (distances, hashed_files, mut unhashed_files) =
+        compute_distances(files, settings, args.entropy_weight);
     if hashed_files.len() < MINIMUM_ITEMS {
         paths = hashed_files;
     } else {
-        let tsp = Tsp::new(distances, hashed_files.len());
-        let tour = Tour::new((0..hashed_files.len()).collect());
-        let indices = optimize_twoopt_from_tour(&tsp, tour)?;
-        for i in indices {
-            // TODO: this feels like an unnecessary clone
-            paths.push(hashed_files.get(i).unwrap().clone());
-        }
+        let tsp = Tsp::from_distances(distances);
+        paths.append(&mut order_hashed_tsp(args, &tsp, &hashed_files)?);
     }
     paths.append(&mut unhashed_files);
     return Ok(paths);
 }
 
-fn order_binsort<'a>(files: Vec<MinhashTarget>) -> Vec<PathBuf> {
-    let (distances, hashed_files, mut unhashed_files) = compute_distances(files);
-    let tsp = Tsp::new(distances, hashed_files.len());
+// The order_hashed_* functions order files that have already been hashed, so that auto can hash a
+// batch and compute its distances once for every algorithm it tries
+fn order_hashed_tsp(
+    args: &Args,
+    tsp: &Tsp<u8>,
+    hashed_files: &[PathBuf],
+) -> Result<Vec<PathBuf>, String> {
+    if hashed_files.len() < MINIMUM_ITEMS {
+        return Ok(hashed_files.to_vec());
+    }
+    order_instance(args, tsp, hashed_files)
+}
+
+// Below this many files, comparing all pairs to find neighbors is cheap enough
+const EXACT_NEIGHBOR_LIMIT: usize = 2000;
+
+fn order_sparse_tsp(args: &Args, files: Vec<MinhashTarget>) -> Result<Vec<PathBuf>, String> {
+    let settings = args.minhash_settings();
+    let (signatures, hashed_files, mut unhashed_files) = hash_targets(files, settings);
+    let mut paths = if hashed_files.len() < MINIMUM_ITEMS {
+        hashed_files
+    } else {
+        let neighbors = if signatures.len() <= EXACT_NEIGHBOR_LIMIT {
+            exact_neighbors(&signatures, NEIGHBOR_SIZE)
+        } else {
+            let index = LshIndex::build(&signatures, args.lsh_bands, args.lsh_rows);
+            lsh_neighbors(&signatures, &index, NEIGHBOR_SIZE)
+        };
+        let tsp = SparseTsp::with_entropy_weight(signatures, neighbors, args.entropy_weight);
+        order_instance(args, &tsp, &hashed_files)?
+    };
+    paths.append(&mut unhashed_files);
+    Ok(paths)
+}
+
+// Solves small instances exactly, and runs local search on larger ones
+fn order_instance<I: TspInstance>(
+    args: &Args,
+    tsp: &I,
+    files: &[PathBuf],
+) -> Result<Vec<PathBuf>, String> {
+    let pin_start = pinned_index(&args.first, files);
+    let pin_end = pinned_index(&args.last, files);
+    let exact = match args.objective() {
+        Objective::Cycle => solve_cycle(tsp),
+        Objective::Path | Objective::Window => solve_path(tsp, pin_start, pin_end),
+    };
+    let indices = match exact {
+        Some(indices) => {
+            log::debug!("Solved {} files exactly", files.len());
+            indices
+        }
+        None => {
+            let order = if args.spectral_seed {
+                spectral_order(tsp)
+            } else {
+                args.construction.build(tsp)
+            };
+            if order.len() >= TWO_LEVEL_THRESHOLD {
+                optimize_tour(args, tsp, TwoLevelTour::new(order), files)?
+            } else {
+                optimize_tour(args, tsp, Tour::new(order), files)?
+            }
+        }
+    };
+    let indices = match args.objective() {
+        Objective::Window => {
+            let sizes: Vec<u64> = files
+                .iter()
+                .map(|path| match path.metadata() {
+                    Ok(metadata) if metadata.is_file() => metadata.len(),
+                    _ => 0,
+                })
+                .collect();
+            let window = args.window() as u64;
+            optimize_window(
+                tsp,
+                &sizes,
+                window,
+                indices,
+                pin_start.is_some(),
+                pin_end.is_some(),
+            )
+        }
+        _ => indices,
+    };
+    Ok(indices.into_iter().map(|i| files[i].clone()).collect())
+}
+
+fn optimize_tour<I: TspInstance, T: CyclicTour>(
+    args: &Args,
+    tsp: &I,
+    tour: T,
+    files: &[PathBuf],
+) -> Result<Vec<usize>, String> {
+    match args.objective() {
+        Objective::Cycle => optimize_twoopt_from_tour(tsp, tour),
+        Objective::Path | Objective::Window => optimize_twoopt_path(
+            tsp,
+            tour,
+            pinned_index(&args.first, files),
+            pinned_index(&args.last, files),
+        ),
+    }
+}
+
+fn order_atsp(args: &Args, files: Vec<MinhashTarget>) -> Result<Vec<PathBuf>, String> {
+    let settings = args.minhash_settings();
+    let (distances, hashed_files, mut unhashed_files) =
+        compute_asymmetric_distances(files, settings, args.entropy_weight);
+    if hashed_files.len() < MINIMUM_ITEMS {
+        let mut paths = hashed_files;
+        paths.append(&mut unhashed_files);
+        return Ok(paths);
+    }
+    let tsp = AsymmetricTsp::new(distances, hashed_files.len());
+    let start = pinned_index(&args.first, &hashed_files);
+    let end = pinned_index(&args.last, &hashed_files);
+    let mut path = tsp.generate_nearest_neighbor_path(start.unwrap_or(0));
+    if let Some(last) = end {
+        path.retain(|&i| i != last);
+        path.push(last);
+    }
+    let indices = optimize_atsp(&tsp, path, start.is_some(), end.is_some());
+    let mut paths: Vec<PathBuf> = indices
+        .into_iter()
+        .map(|i| hashed_files[i].clone())
+        .collect();
+    paths.append(&mut unhashed_files);
+    Ok(paths)
+}
+
+// Clusters the files, then emits the leaves of the dendrogram so that neighboring files are as
+// similar as the tree allows. Pinned files are handled afterwards by pin_ends.
+fn order_hierarchical(args: &Args, files: Vec<MinhashTarget>) -> Vec<PathBuf> {
+    let settings = args.minhash_settings();
+    let (distances, hashed_files, mut unhashed_files) =
+        compute_distances(files, settings, args.entropy_weight);
+    let mut paths = order_hashed_hierarchical(&distances, &hashed_files);
+    paths.append(&mut unhashed_files);
+    paths
+}
+
+fn order_hashed_hierarchical(
+    distances: &TriangularDistances<u8>,
+    hashed_files: &[PathBuf],
+) -> Vec<PathBuf> {
+    let dendrogram = Dendrogram::average_linkage(distances);
+    dendrogram
+        .optimal_leaf_order(distances)
+        .into_iter()
+        .map(|i| hashed_files[i].clone())
+        .collect()
+}
+
+fn order_spectral(args: &Args, files: Vec<MinhashTarget>) -> Vec<PathBuf> {
+    let settings = args.minhash_settings();
+    let (distances, hashed_files, mut unhashed_files) =
+        compute_distances(files, settings, args.entropy_weight);
+    let tsp = Tsp::from_distances(distances);
+    let mut paths = order_hashed_spectral(&tsp, &hashed_files);
+    paths.append(&mut unhashed_files);
+    paths
+}
+
+fn order_hashed_spectral(tsp: &Tsp<u8>, hashed_files: &[PathBuf]) -> Vec<PathBuf> {
+    spectral_order(tsp)
+        .into_iter()
+        .map(|i| hashed_files[i].clone())
+        .collect()
+}
+
+fn order_signature_sort(args: &Args, files: Vec<MinhashTarget>) -> Result<Vec<PathBuf>, String> {
+    let settings = args.minhash_settings();
+    let (signatures, hashed_files, mut unhashed_files) = hash_targets(files, settings);
+    let mut paths = order_hashed_signature_sort(args, &signatures, &hashed_files)?;
+    paths.append(&mut unhashed_files);
+    Ok(paths)
+}
+
+fn order_hashed_signature_sort(
+    args: &Args,
+    signatures: &[Minhash],
+    hashed_files: &[PathBuf],
+) -> Result<Vec<PathBuf>, String> {
+    Ok(signature_sort(signatures, args.signature_window)?
+        .into_iter()
+        .map(|i| hashed_files[i].clone())
+        .collect())
+}
+
+// The algorithms auto tries, cheapest first, so the time budget cuts off the expensive ones
+const AUTO_CANDIDATES: [Algorithm; 5] = [
+    Algorithm::OnlyExtensions,
+    Algorithm::SignatureSort,
+    Algorithm::Spectral,
+    Algorithm::Hierarchical,
+    Algorithm::Tsp,
+];
+
+// Orders the batch with each candidate algorithm that can handle its size, while the time budget
+// lasts, and keeps the ordering with the lowest score
+fn order_auto(args: &Args, files: Vec<MinhashTarget>) -> Result<Vec<PathBuf>, String> {
+    let start = std::time::Instant::now();
+    let count = files.len();
+    let listed = convert_to_pathbufs(files.clone())?;
+    let (signatures, hashed_files, unhashed_files) = hash_targets(files, args.minhash_settings());
+    // Only spectral, hierarchical, tsp and the length score need distances, so they aren't
+    // computed if the time budget runs out first
+    let tsp = OnceCell::new();
+    let tsp = || {
+        tsp.get_or_init(|| {
+            Tsp::from_distances(signature_distances(&signatures, args.entropy_weight))
+        })
+    };
+    let index: HashMap<&PathBuf, usize> = hashed_files
+        .iter()
+        .enumerate()
+        .map(|(i, p)| (p, i))
+        .collect();
+    let score = |order: &[PathBuf]| -> Result<u64, String> {
+        match args.auto_score {
+            AutoScore::Length => {
+                let distances = &tsp().distances;
+                let indices: Vec<usize> =
+                    order.iter().filter_map(|p| index.get(p).copied()).collect();
+                Ok(indices
+                    .windows(2)
+                    .map(|pair| distances.get(pair[0], pair[1]) as u64)
+                    .sum())
+            }
+            AutoScore::Estimate => estimate_compressed_size(order, args.window()),
+        }
+    };
+
+    let mut best: Option<(Algorithm, Vec<PathBuf>, u64)> = None;
+    let mut scores = Vec::new();
+    for candidate in AUTO_CANDIDATES {
+        if count > candidate.max_batch() {
+            continue;
+        }
+        if best.is_some() && start.elapsed().as_secs_f64() >= args.auto_budget {
+            log::info!("Auto ran out of time after {} algorithms", scores.len());
+            break;
+        }
+        let mut order = match candidate {
+            Algorithm::OnlyExtensions => listed.clone(),
+            Algorithm::SignatureSort => {
+                order_hashed_signature_sort(args, &signatures, &hashed_files)?
+            }
+            Algorithm::Spectral => order_hashed_spectral(tsp(), &hashed_files),
+            Algorithm::Hierarchical => order_hashed_hierarchical(&tsp().distances, &hashed_files),
+            Algorithm::Tsp => order_hashed_tsp(args, tsp(), &hashed_files)?,
+            _ => unreachable!("{:?} is not an auto candidate", candidate),
+        };
+        if !matches!(candidate, Algorithm::OnlyExtensions) {
+            order.extend(unhashed_files.iter().cloned());
+        }
+        let candidate_score = score(&order)?;
+        scores.push((candidate, candidate_score));
+        if best
+            .as_ref()
+            .is_none_or(|(_, _, best_score)| candidate_score < *best_score)
+        {
+            best = Some((candidate, order, candidate_score));
+        }
+    }
+    let (algorithm, order, _) = best.unwrap();
+    log::info!(
+        "Auto chose {:?} for a batch of {} files, scores by {:?}: {:?}",
+        algorithm,
+        count,
+        args.auto_score,
+        scores
+    );
+    Ok(order)
+}
+
+fn order_binsort<'a>(args: &Args, files: Vec<MinhashTarget>) -> Vec<PathBuf> {
+    let settings = args.minhash_settings();
+    let (distances, hashed_files, mut unhashed_files) =
+        compute_distances(files, settings, args.entropy_weight);
+    let tsp = Tsp::from_distances(distances);
     let indices = optimize_binsort(&tsp);
     let mut paths = vec![];
     for i in indices {
@@ -334,9 +1408,11 @@ fn by_filename<'a>(dir: &Path) -> Result<HashMap<OsString, Vec<MinhashTarget>>,
 mod tests {
 
     use crate::testutils::*;
+    use crate::window::WINDOW_FILE_LIMIT;
     use crate::*;
 
-    use rand::Rng;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
     use std::collections::HashSet;
     use std::fs::{create_dir, File};
     use std::io::Write;
@@ -380,11 +1456,8 @@ mod tests {
         let path = &temp_dir.into_path();
         let directory = path.to_str().unwrap().to_string();
 
-        let args = Args {
-            directory: directory,
-            algorithm: Algorithm::Tsp,
-        };
-        let ordered_files = load_and_order(args).unwrap();
+        let args = Args::parse_from(["simsort", &directory, "tsp"]);
+        let ordered_files = load_and_order(&args).unwrap().0;
         assert_eq!(11, ordered_files.len());
         for i in 0..10 {
             let mut contained = false;
@@ -420,11 +1493,8 @@ mod tests {
         let path = &temp_dir.into_path();
         let directory = path.to_str().unwrap().to_string();
 
-        let args = Args {
-            directory: directory,
-            algorithm: Algorithm::Tsp,
-        };
-        let ordered_files = load_and_order(args).unwrap();
+        let args = Args::parse_from(["simsort", &directory, "tsp"]);
+        let ordered_files = load_and_order(&args).unwrap().0;
 
         // 10 files, 1 directory
         assert_eq!(11, ordered_files.len());
@@ -459,20 +1529,492 @@ mod tests {
     }
 
     #[test]
-    fn run_can_run() {
+    fn load_and_order_pins_first_and_last() {
         let temp_dir = setup_directory(6);
-        let args = Args {
-            directory: temp_dir
-                .into_path()
-                .as_os_str()
-                .to_str()
-                .unwrap()
-                .to_string(),
-            algorithm: Algorithm::Tsp,
+        let directory = temp_dir.path().to_str().unwrap().to_string();
+        let first = temp_dir.path().join("populated_directory").join("3");
+        let last = temp_dir.path().join("populated_directory").join("1");
+        let args = Args::parse_from([
+            "simsort",
+            &directory,
+            "tsp",
+            "--first",
+            first.to_str().unwrap(),
+            "--last",
+            last.to_str().unwrap(),
+        ]);
+        let ordered_files = load_and_order(&args).unwrap().0;
+        assert_eq!(10, ordered_files.len());
+        assert_eq!(first, ordered_files[0]);
+        assert_eq!(last, ordered_files[9]);
+    }
+
+    #[test]
+    fn load_and_order_asymmetric_tsp_returns_all_files() {
+        let temp_dir = setup_directory(8);
+        let directory = temp_dir.path().to_str().unwrap().to_string();
+        let args = Args::parse_from(["simsort", &directory, "asymmetric-tsp"]);
+        let ordered_files = load_and_order(&args).unwrap().0;
+        assert_eq!(12, ordered_files.len());
+        let unique: HashSet<&PathBuf> = ordered_files.iter().collect();
+        assert_eq!(12, unique.len());
+    }
+
+    #[test]
+    fn load_and_order_sparse_tsp_returns_all_files() {
+        let temp_dir = setup_directory(8);
+        let directory = temp_dir.path().to_str().unwrap().to_string();
+        let args = Args::parse_from(["simsort", &directory, "sparse-tsp"]);
+        let ordered_files = load_and_order(&args).unwrap().0;
+        assert_eq!(12, ordered_files.len());
+        let unique: HashSet<&PathBuf> = ordered_files.iter().collect();
+        assert_eq!(12, unique.len());
+    }
+
+    #[test]
+    fn load_and_order_hierarchical_returns_all_files() {
+        let temp_dir = setup_directory(8);
+        let directory = temp_dir.path().to_str().unwrap().to_string();
+        let args = Args::parse_from(["simsort", &directory, "hierarchical"]);
+        let ordered_files = load_and_order(&args).unwrap().0;
+        assert_eq!(12, ordered_files.len());
+        let unique: HashSet<&PathBuf> = ordered_files.iter().collect();
+        assert_eq!(12, unique.len());
+    }
+
+    #[test]
+    fn load_and_order_spectral_returns_all_files() {
+        let temp_dir = setup_directory(8);
+        let directory = temp_dir.path().to_str().unwrap().to_string();
+        let args = Args::parse_from(["simsort", &directory, "spectral"]);
+        let ordered_files = load_and_order(&args).unwrap().0;
+        assert_eq!(12, ordered_files.len());
+        let unique: HashSet<&PathBuf> = ordered_files.iter().collect();
+        assert_eq!(12, unique.len());
+    }
+
+    #[test]
+    fn load_and_order_tsp_with_spectral_seed_returns_all_files() {
+        let temp_dir = setup_directory(8);
+        let directory = temp_dir.path().to_str().unwrap().to_string();
+        let args = Args::parse_from(["simsort", &directory, "tsp", "--spectral-seed"]);
+        let ordered_files = load_and_order(&args).unwrap().0;
+        assert_eq!(12, ordered_files.len());
+        let unique: HashSet<&PathBuf> = ordered_files.iter().collect();
+        assert_eq!(12, unique.len());
+    }
+
+    #[test]
+    fn load_and_order_signature_sort_returns_all_files() {
+        let temp_dir = setup_directory(8);
+        let directory = temp_dir.path().to_str().unwrap().to_string();
+        let args = Args::parse_from(["simsort", &directory, "signature-sort"]);
+        let ordered_files = load_and_order(&args).unwrap().0;
+        assert_eq!(12, ordered_files.len());
+        let unique: HashSet<&PathBuf> = ordered_files.iter().collect();
+        assert_eq!(12, unique.len());
+    }
+
+    #[test]
+    fn load_and_order_with_best_construction_returns_all_files() {
+        let temp_dir = setup_directory(8);
+        let directory = temp_dir.path().to_str().unwrap().to_string();
+        let args = Args::parse_from(["simsort", &directory, "tsp", "--construction", "best"]);
+        let ordered_files = load_and_order(&args).unwrap().0;
+        assert_eq!(12, ordered_files.len());
+        let unique: HashSet<&PathBuf> = ordered_files.iter().collect();
+        assert_eq!(12, unique.len());
+    }
+
+    // Files on a line, each a neighbor of the files on either side, cheap to build at any size
+    struct Line {
+        neighbors: Vec<Vec<usize>>,
+    }
+
+    impl TspInstance for Line {
+        fn count(&self) -> usize {
+            self.neighbors.len()
+        }
+
+        fn retrieve_distance(&self, t1: usize, t2: usize) -> u64 {
+            t1.abs_diff(t2) as u64
+        }
+
+        fn neighbors(&self, index: usize) -> &[usize] {
+            &self.neighbors[index]
+        }
+    }
+
+    #[test]
+    fn large_batches_skip_farthest_insertion() {
+        let count = FARTHEST_INSERTION_LIMIT + 1;
+        let neighbors = (0..count)
+            .map(|i| {
+                [i.wrapping_sub(1), i + 1]
+                    .into_iter()
+                    .filter(|&j| j < count)
+                    .collect()
+            })
+            .collect();
+        let line = Line { neighbors };
+        for construction in [Construction::FarthestInsertion, Construction::Best] {
+            let order = construction.build(&line);
+            check_permutation(&order, count - 1);
+            assert_eq!(count as u64 - 1, line.calculate_distance(&order));
+        }
+    }
+
+    #[test]
+    fn load_and_order_auto_returns_all_files() {
+        let temp_dir = setup_directory(8);
+        let directory = temp_dir.path().to_str().unwrap().to_string();
+        for score in ["length", "estimate"] {
+            let args = Args::parse_from(["simsort", &directory, "auto", "--auto-score", score]);
+            let ordered_files = load_and_order(&args).unwrap().0;
+            assert_eq!(12, ordered_files.len());
+            let unique: HashSet<&PathBuf> = ordered_files.iter().collect();
+            assert_eq!(12, unique.len());
+        }
+    }
+
+    #[test]
+    fn load_and_order_window_objective_keeps_pins() {
+        let temp_dir = setup_directory(8);
+        let directory = temp_dir.path().to_str().unwrap().to_string();
+        let first = temp_dir.path().join("populated_directory/3");
+        let args = Args::parse_from([
+            "simsort",
+            &directory,
+            "sparse-tsp",
+            "--objective",
+            "window",
+            "--window",
+            "64M",
+            "--first",
+            first.to_str().unwrap(),
+        ]);
+        let ordered_files = load_and_order(&args).unwrap().0;
+        assert_eq!(12, ordered_files.len());
+        assert_eq!(first, ordered_files[0]);
+        let unique: HashSet<&PathBuf> = ordered_files.iter().collect();
+        assert_eq!(12, unique.len());
+    }
+
+    #[test]
+    fn load_and_order_with_each_compressor_returns_all_files() {
+        let temp_dir = setup_directory(8);
+        let directory = temp_dir.path().to_str().unwrap().to_string();
+        for profile in Profile::value_variants() {
+            let name = profile.to_possible_value().unwrap().get_name().to_string();
+            let args = Args::parse_from(["simsort", &directory, "tsp", "--compressor", &name]);
+            let ordered_files = load_and_order(&args).unwrap().0;
+            assert_eq!(12, ordered_files.len());
+            let unique: HashSet<&PathBuf> = ordered_files.iter().collect();
+            assert_eq!(12, unique.len());
+        }
+    }
+
+    #[test]
+    fn compressor_defaults_can_be_overridden() {
+        let args = Args::parse_from(["simsort", "dir", "tsp", "--compressor", "xz"]);
+        assert_eq!(Objective::Window, args.objective());
+        assert_eq!(8 << 20, args.window());
+        let args = Args::parse_from([
+            "simsort",
+            "dir",
+            "tsp",
+            "--compressor",
+            "xz",
+            "--objective",
+            "path",
+            "--shingle-size",
+            "12",
+        ]);
+        assert_eq!(Objective::Path, args.objective());
+        assert_eq!(12, args.minhash_settings().shingle_size);
+        // bzip2's blocks aren't a sliding window, so it keeps the path objective
+        let args = Args::parse_from(["simsort", "dir", "tsp", "--compressor", "bzip2"]);
+        assert_eq!(Objective::Path, args.objective());
+        assert_eq!(4, args.minhash_settings().shingle_size);
+        let args = Args::parse_from(["simsort", "dir", "tsp", "--compressor", "brotli"]);
+        assert!(args.guard_compressor().is_err());
+    }
+
+    #[test]
+    fn window_profiles_cap_sparse_batches() {
+        let parse = |words: &[&str]| Args::parse_from(["simsort", "dir"].iter().chain(words));
+        assert_eq!(
+            WINDOW_FILE_LIMIT,
+            parse(&["sparse-tsp", "--compressor", "xz"]).max_batch()
+        );
+        assert_eq!(
+            500000,
+            parse(&["sparse-tsp", "--compressor", "gzip"]).max_batch()
+        );
+        let path = parse(&["sparse-tsp", "--compressor", "xz", "--objective", "path"]);
+        assert_eq!(500000, path.max_batch());
+        // Signature sort never optimizes the window, so it keeps its unlimited batches
+        let signature_sort = parse(&["signature-sort", "--compressor", "xz"]);
+        assert_eq!(usize::MAX, signature_sort.max_batch());
+    }
+
+    #[test]
+    fn blocks_cover_the_ordering() {
+        let temp_dir = setup_directory(8);
+        let directory = temp_dir.path().to_str().unwrap().to_string();
+        let output_dir = tempdir().unwrap();
+        let prefix = output_dir.path().join("part");
+        let prefix = prefix.to_str().unwrap();
+        let args = Args::parse_from([
+            "simsort",
+            &directory,
+            "tsp",
+            "--blocks",
+            "3",
+            "--block-prefix",
+            prefix,
+        ]);
+        let (ordered_files, signatures) = load_and_order(&args).unwrap();
+        // Every file was hashed to order it, so none has to be hashed again to cut blocks
+        assert_eq!(ordered_files.len(), signatures.len());
+        write_blocks(&args, &PathBuf::from("/"), &ordered_files, &signatures).unwrap();
+        let mut listed = Vec::new();
+        for number in 0..3 {
+            let list = std::fs::read_to_string(format!("{}-{}.txt", prefix, number)).unwrap();
+            listed.extend(list.lines().map(|line| Path::new("/").join(line)));
+        }
+        assert_eq!(ordered_files, listed);
+
+        let args = Args::parse_from([
+            "simsort",
+            &directory,
+            "tsp",
+            "--blocks",
+            "3",
+            "--block-format",
+            "sizes",
+            "--block-prefix",
+            prefix,
+        ]);
+        write_blocks(&args, &PathBuf::from("/"), &ordered_files, &signatures).unwrap();
+        let sizes = std::fs::read_to_string(format!("{}.sizes", prefix)).unwrap();
+        let total: u64 = sizes
+            .trim()
+            .split(',')
+            .map(|size| size.parse::<u64>().unwrap())
+            .sum();
+        let expected: u64 = ordered_files
+            .iter()
+            .map(|path| tar_entry_size(path, &output_path(path, &PathBuf::from("/")).unwrap()))
+            .sum();
+        assert_eq!(expected, total);
+    }
+
+    #[test]
+    fn compressed_formats_are_grouped_with_uniform_files() {
+        let temp_dir = tempdir().unwrap();
+        let text = b"some text that compresses well, ".repeat(20);
+        let files: [(&str, &[u8]); 6] = [
+            ("a.gz", b"\x1f\x8b\x08\x00"),
+            ("b.png", b"\x89PNG\r\n\x1a\n"),
+            ("c.gz", b"\x1f\x8b\x08\x08"),
+            ("d.txt", b""),
+            ("e.txt", b""),
+            ("f.txt", b""),
+        ];
+        for (name, header) in files {
+            let mut file = File::create(temp_dir.path().join(name)).unwrap();
+            file.write_all(header).unwrap();
+            file.write_all(&text).unwrap();
+        }
+        let directory = temp_dir.path().to_str().unwrap().to_string();
+        let extension = |path: &PathBuf| path.extension().unwrap().to_str().unwrap().to_string();
+
+        let args = Args::parse_from(["simsort", &directory, "tsp"]);
+        let ordered_files = load_and_order(&args).unwrap().0;
+        assert_eq!(7, ordered_files.len());
+        let first: Vec<String> = ordered_files[..3].iter().map(extension).collect();
+        assert_eq!(vec!["gz", "gz", "png"], first);
+
+        let args = Args::parse_from(["simsort", &directory, "tsp", "--compressor", "xz"]);
+        let ordered_files = load_and_order(&args).unwrap().0;
+        let last: Vec<String> = ordered_files[4..].iter().map(extension).collect();
+        assert_eq!(vec!["gz", "gz", "png"], last);
+    }
+
+    #[test]
+    fn binaries_are_split_by_entropy_and_text() {
+        let temp_dir = tempdir().unwrap();
+        let mut rng = StdRng::seed_from_u64(50);
+        // Sparse binaries use three byte values, dense ones 32, which is still too few to look
+        // uniform
+        for i in 0..3 {
+            let sparse: Vec<u8> = (0..40000)
+                .map(|_| [0, 1, 255][rng.gen_range(0..3)])
+                .collect();
+            std::fs::write(temp_dir.path().join(format!("sparse{}", i)), sparse).unwrap();
+            let dense: Vec<u8> = (0..40000).map(|_| rng.gen_range(0..32) | 0x80).collect();
+            std::fs::write(temp_dir.path().join(format!("dense{}", i)), dense).unwrap();
+            // Text in utf-8 has about the entropy of the dense binaries, but is mostly printable
+            let words = ["café ", "simsort ", "orders ", "similar ", "files "];
+            let text: String = (0..5000).map(|_| words[rng.gen_range(0..5)]).collect();
+            std::fs::write(temp_dir.path().join(format!("texts{}", i)), text).unwrap();
+        }
+        let directory = temp_dir.path().to_str().unwrap().to_string();
+        for weight in ["0", "0.5"] {
+            let args = Args::parse_from(["simsort", &directory, "tsp", "--entropy-weight", weight]);
+            let ordered_files = load_and_order(&args).unwrap().0;
+            assert_eq!(10, ordered_files.len());
+            let names: Vec<String> = ordered_files[..9]
+                .iter()
+                .map(|path| path.file_name().unwrap().to_str().unwrap()[..5].to_string())
+                .collect();
+            let mut expected = vec!["spars"; 3];
+            expected.extend(["dense"; 3]);
+            expected.extend(["texts"; 3]);
+            assert_eq!(expected, names);
+        }
+    }
+
+    #[test]
+    fn entropy_weight_adds_to_distances() {
+        let temp_dir = tempdir().unwrap();
+        let mut rng = StdRng::seed_from_u64(51);
+        // The same text, but with random bytes after it in the second file, which raise its
+        // entropy without making it look much less similar
+        let words = ["simsort ", "orders ", "similar ", "files "];
+        let text: Vec<u8> = (0..4000)
+            .flat_map(|_| words[rng.gen_range(0..4)].bytes())
+            .collect();
+        let mut noisy = text.clone();
+        noisy.extend((0..text.len() / 10).map(|_| rng.gen::<u8>()));
+        let mut targets = Vec::new();
+        for (name, bytes) in [("text", text), ("noisy", noisy)] {
+            let path = temp_dir.path().join(name);
+            std::fs::write(&path, bytes).unwrap();
+            targets.push(MinhashTarget::File(path));
+        }
+        let settings = MinhashSettings::default();
+        let (unweighted, _, _) = compute_distances(targets.clone(), settings, 0.0);
+        let (weighted, _, _) = compute_distances(targets.clone(), settings, 0.5);
+        assert!(unweighted.get(0, 1) < 200, "{}", unweighted.get(0, 1));
+        assert!(weighted.get(0, 1) > unweighted.get(0, 1));
+
+        // Sparse tsp weighs the distances it computes the same way
+        let (signatures, _, _) = hash_targets(targets, settings);
+        let tsp = SparseTsp::with_entropy_weight(signatures, vec![vec![], vec![]], 0.5);
+        assert_eq!(weighted.get(0, 1) as u64, tsp.retrieve_distance(0, 1));
+    }
+
+    #[test]
+    fn entropy_weight_is_checked() {
+        let temp_dir = setup_directory(4);
+        let directory = temp_dir.path().to_str().unwrap().to_string();
+        let order_with = |algorithm: &str, weight: &str| {
+            order(&Args::parse_from([
+                "simsort",
+                &directory,
+                algorithm,
+                "--entropy-weight",
+                weight,
+            ]))
         };
+        assert!(order_with("sparse-tsp", "0.5").is_ok());
+        assert!(order_with("signature-sort", "0").is_ok());
+        assert!(order_with("signature-sort", "0.5").is_err());
+        assert!(order_with("tsp", "1.5").is_err());
+        assert!(order_with("tsp", "NaN").is_err());
+    }
+
+    #[test]
+    fn asymmetric_batches_are_never_reversed() {
+        let mut rng = StdRng::seed_from_u64(32);
+        let contents: Vec<Vec<u8>> = (0..3)
+            .map(|_| (0..2048).map(|_| rng.gen()).collect())
+            .collect();
+        // Both batches end in a copy of the same file, so they join best with one reversed
+        let signatures: Vec<Minhash> = [0, 1, 2, 1]
+            .iter()
+            .map(|&c| minhash_bytes(&contents[c]))
+            .collect();
+        let paths: Vec<PathBuf> = ["a", "b1", "c", "b2"].iter().map(PathBuf::from).collect();
+        let signature_index = paths.iter().cloned().zip(0..).collect();
+        let batches = vec![paths[..2].to_vec(), paths[2..].to_vec()];
+        let joined = join_batches(
+            &Algorithm::Tsp,
+            batches.clone(),
+            &signature_index,
+            &signatures,
+        );
+        let copies = |pair: &[PathBuf]| pair.contains(&paths[1]) && pair.contains(&paths[3]);
+        assert!(joined.windows(2).any(copies), "{:?}", joined);
+        let joined = join_batches(
+            &Algorithm::AsymmetricTsp,
+            batches.clone(),
+            &signature_index,
+            &signatures,
+        );
+        assert!(joined == batches.concat() || joined == [&paths[2..], &paths[..2]].concat());
+    }
+
+    #[test]
+    fn lsh_parameters_must_be_positive() {
+        for option in ["--lsh-bands", "--lsh-rows"] {
+            assert!(Args::try_parse_from(["simsort", ".", "sparse-tsp", option, "0"]).is_err());
+            assert!(Args::try_parse_from(["simsort", ".", "sparse-tsp", option, "2"]).is_ok());
+        }
+    }
+
+    #[test]
+    fn guard_prefers_walk_order_on_ties() {
+        // Every file has the same contents, so no order compresses better than the walk order
+        let temp_dir = setup_directory(8);
+        let directory = temp_dir.path().to_str().unwrap().to_string();
+        let walk: Vec<PathBuf> = WalkDir::new(&directory)
+            .into_iter()
+            .map(|e| e.unwrap().path().to_path_buf())
+            .collect();
+        let args = Args::parse_from(["simsort", &directory, "tsp", "--guard"]);
+        assert_eq!(walk, load_and_order(&args).unwrap().0);
+    }
+
+    #[test]
+    fn guard_by_batch_returns_all_files() {
+        let temp_dir = setup_directory(8);
+        let directory = temp_dir.path().to_str().unwrap().to_string();
+        let args = Args::parse_from([
+            "simsort",
+            &directory,
+            "tsp",
+            "--guard",
+            "trial",
+            "--guard-scope",
+            "batch",
+        ]);
+        let ordered_files = load_and_order(&args).unwrap().0;
+        assert_eq!(12, ordered_files.len());
+        let unique: HashSet<&PathBuf> = ordered_files.iter().collect();
+        assert_eq!(12, unique.len());
+    }
+
+    #[test]
+    fn run_can_run() {
+        let temp_dir = setup_directory(6);
+        let directory = temp_dir.into_path().to_str().unwrap().to_string();
+        let args = Args::parse_from(["simsort", &directory, "tsp"]);
         let _ = run(args);
     }
 
+    #[test]
+    fn run_can_print_hints() {
+        let temp_dir = setup_directory(6);
+        let directory = temp_dir.path().to_str().unwrap().to_string();
+        for output in ["hints", "zip-suffixes", "store-list"] {
+            let args = Args::parse_from(["simsort", &directory, "tsp", "--output", output]);
+            assert_eq!(Ok(()), run(args));
+        }
+    }
+
     #[test]
     fn by_filename_returns_the_same_files_as_tar() {
         let tempdir = setup_directory(1);
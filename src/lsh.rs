@@ -0,0 +1,228 @@
+use std::collections::{BinaryHeap, HashMap};
+
+use crate::minhash::{similarity_to_distance, Minhash};
+
+pub const DEFAULT_BANDS: usize = 32;
+pub const DEFAULT_ROWS: usize = 4;
+
+// Buckets shared by many files (e.g. many copies of the same file) would make candidate lists
+// quadratic, so we only take this many candidates from any one bucket.
+const MAX_BUCKET_CANDIDATES: usize = 64;
+
+/**
+ * A locality-sensitive hashing index over minhash signatures, for finding similar files without
+ * comparing every pair.
+ *
+ * Our signatures are bottom-k sketches rather than k independent minhashes, so we can't band the
+ * features directly: a single differing feature shifts the position of every feature after it.
+ * Instead, each row of each band takes the minimum of the features under its own hash function.
+ * Two files collide on a row with probability roughly equal to the jaccard similarity of their
+ * sketches, so they share a band with probability 1 - (1 - s^rows)^bands. More rows make the
+ * index stricter, more bands make it more forgiving.
+ */
+pub struct LshIndex {
+    bands: usize,
+    rows: usize,
+    buckets: Vec<HashMap<u64, Vec<usize>>>,
+    keys: Vec<Vec<u64>>,
+}
+
+impl LshIndex {
+    pub fn new(bands: usize, rows: usize) -> LshIndex {
+        assert!(bands > 0 && rows > 0);
+        LshIndex {
+            bands,
+            rows,
+            buckets: (0..bands).map(|_| HashMap::new()).collect(),
+            keys: Vec::new(),
+        }
+    }
+
+    pub fn build(signatures: &[Minhash], bands: usize, rows: usize) -> LshIndex {
+        let mut index = LshIndex::new(bands, rows);
+        for signature in signatures {
+            index.insert(signature);
+        }
+        index
+    }
+
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    /// Adds a signature to the index, returning its id. Ids are assigned in insertion order.
+    pub fn insert(&mut self, signature: &Minhash) -> usize {
+        let id = self.keys.len();
+        let keys = self.band_keys(signature);
+        for (band, &key) in keys.iter().enumerate() {
+            self.buckets[band].entry(key).or_default().push(id);
+        }
+        self.keys.push(keys);
+        id
+    }
+
+    /// Ids of indexed signatures that share at least one band with `signature`.
+    pub fn candidates(&self, signature: &Minhash) -> Vec<usize> {
+        self.candidates_for_keys(&self.band_keys(signature), None)
+    }
+
+    /// Ids of indexed signatures that share at least one band with the signature with this id.
+    pub fn candidates_for(&self, id: usize) -> Vec<usize> {
+        self.candidates_for_keys(&self.keys[id], Some(id))
+    }
+
+    fn candidates_for_keys(&self, keys: &[u64], exclude: Option<usize>) -> Vec<usize> {
+        let mut candidates = Vec::new();
+        for (band, key) in keys.iter().enumerate() {
+            if let Some(bucket) = self.buckets[band].get(key) {
+                candidates.extend(
+                    bucket
+                        .iter()
+                        .filter(|&&id| Some(id) != exclude)
+                        .take(MAX_BUCKET_CANDIDATES),
+                );
+            }
+        }
+        candidates.sort_unstable();
+        candidates.dedup();
+        candidates
+    }
+
+    fn band_keys(&self, signature: &Minhash) -> Vec<u64> {
+        let features = signature.features();
+        if features.is_empty() {
+            return Vec::new();
+        }
+        (0..self.bands)
+            .map(|band| {
+                let mut key = band as u64;
+                for row in 0..self.rows {
+                    let seed = mix((band * self.rows + row) as u64 + 1);
+                    let row_min = features
+                        .iter()
+                        .map(|&f| mix(f as u64 ^ seed))
+                        .min()
+                        .unwrap();
+                    key = mix(key ^ row_min);
+                }
+                key
+            })
+            .collect()
+    }
+}
+
+// splitmix64's finalizer, a cheap way to get independent looking hash functions from seeds
+fn mix(value: u64) -> u64 {
+    let mut z = value.wrapping_add(0x9e3779b97f4a7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^ (z >> 31)
+}
+
+/// Finds up to k near neighbors for every signature, scoring only the candidates the index
+/// returns. Signatures with no candidates get an empty list.
+pub fn lsh_neighbors(signatures: &[Minhash], index: &LshIndex, k: usize) -> Vec<Vec<usize>> {
+    (0..signatures.len())
+        .map(|i| nearest_candidates(&signatures[i], index.candidates_for(i), signatures, k))
+        .collect()
+}
+
+/// Ranks candidate ids by their distance to `signature`, keeping the closest k.
+pub fn nearest_candidates(
+    signature: &Minhash,
+    candidates: Vec<usize>,
+    signatures: &[Minhash],
+    k: usize,
+) -> Vec<usize> {
+    let mut heap = BinaryHeap::new();
+    for j in candidates {
+        let distance = similarity_to_distance(signature.score(&signatures[j]));
+        if heap.len() < k {
+            heap.push((distance, j));
+        } else if let Some(&(farthest, _)) = heap.peek() {
+            if distance < farthest {
+                heap.pop();
+                heap.push((distance, j));
+            }
+        }
+    }
+    heap.into_sorted_vec().into_iter().map(|(_, j)| j).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::lsh::*;
+    use crate::minhash::*;
+
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+    use std::fs::File;
+    use std::io::Write;
+    use tempfile::tempdir;
+
+    // Groups of files that are small edits of a shared random base, sharing nothing across groups
+    fn build_signatures(groups: usize, per_group: usize) -> Vec<Minhash> {
+        let mut rng = StdRng::seed_from_u64(30);
+        let temp_dir = tempdir().unwrap();
+        let mut signatures = Vec::new();
+        for group in 0..groups {
+            let base: Vec<u8> = (0..4096).map(|_| rng.gen()).collect();
+            for member in 0..per_group {
+                let mut bytes = base.clone();
+                for _ in 0..4 {
+                    let position = rng.gen_range(0..bytes.len());
+                    bytes[position] = rng.gen();
+                }
+                let path = temp_dir.path().join(format!("{}_{}", group, member));
+                File::create(&path).unwrap().write_all(&bytes).unwrap();
+                signatures.push(minhash_stream(&MinhashTarget::File(path)).unwrap());
+            }
+        }
+        signatures
+    }
+
+    #[test]
+    fn similar_files_are_candidates() {
+        let signatures = build_signatures(6, 5);
+        let index = LshIndex::build(&signatures, DEFAULT_BANDS, DEFAULT_ROWS);
+        assert_eq!(30, index.len());
+        for i in 0..30 {
+            let candidates = index.candidates_for(i);
+            assert!(!candidates.contains(&i));
+            for j in 0..30 {
+                if i != j && i / 5 == j / 5 {
+                    assert!(
+                        candidates.contains(&j),
+                        "{} should be a candidate for {}",
+                        j,
+                        i
+                    );
+                }
+            }
+            assert!(candidates.iter().all(|&j| j / 5 == i / 5));
+        }
+    }
+
+    #[test]
+    fn query_finds_indexed_copy() {
+        let signatures = build_signatures(3, 2);
+        let index = LshIndex::build(&signatures[1..], DEFAULT_BANDS, DEFAULT_ROWS);
+        // signature 0 isn't indexed, but its group mate is at id 0
+        assert_eq!(vec![0], index.candidates(&signatures[0]));
+    }
+
+    #[test]
+    fn lsh_neighbors_stay_in_group() {
+        let signatures = build_signatures(4, 6);
+        let index = LshIndex::build(&signatures, 16, 2);
+        let neighbors = lsh_neighbors(&signatures, &index, 3);
+        for (i, list) in neighbors.iter().enumerate() {
+            assert_eq!(3, list.len());
+            assert!(list.iter().all(|&j| j / 6 == i / 6));
+        }
+    }
+}
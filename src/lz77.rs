@@ -0,0 +1,312 @@
+use std::fs::File;
+use std::io::{copy, Write};
+use std::path::PathBuf;
+
+// gzip/deflate can refer back 32 KiB. xz and zstd's long mode reach much further; 64 MiB is the
+// largest window we'll simulate, since we keep up to twice the window in memory.
+pub const GZIP_WINDOW: usize = 32 * 1024;
+pub const MAX_WINDOW: usize = 64 * 1024 * 1024;
+const MIN_WINDOW: usize = 1024;
+
+const MIN_MATCH: usize = 4;
+const MAX_MATCH: usize = 258;
+// How many earlier occurrences of a hash we try before settling for the longest match so far
+const MAX_CHAIN: usize = 32;
+
+// Rough prices, in bits, of a match token before its length and distance are spelled out.
+// Deflate spends about this much on the Huffman codes for a length and distance pair.
+const MATCH_BITS: f64 = 12.0;
+
+/**
+ * Estimates how many bytes an LZ77 compressor would turn a stream into, without writing out the
+ * compressed stream. Bytes go through a greedy parse against a hash-chained history of `window`
+ * bytes, the way zlib finds matches. Matches are priced by the logarithms of their length and
+ * distance, and literals by an adaptive order-0 model of the literals seen so far, which stands
+ * in for the entropy coding stage.
+ *
+ * The absolute numbers are only loosely tied to any real compressor. What matters is that
+ * putting similar files within a window of each other lowers the estimate, which is exactly what
+ * tour length can't see: two files can be close in minhash distance and still be too far apart
+ * in the stream for the compressor to use one against the other.
+ */
+pub struct Lz77Estimator {
+    window: usize,
+    // The history still in reach, plus whatever hasn't been parsed yet. buffer[0] is at stream
+    // position base.
+    buffer: Vec<u8>,
+    base: usize,
+    parsed: usize,
+    hash_bits: u32,
+    // Stream position + 1 of the latest occurrence of each hash, 0 if none
+    head: Vec<usize>,
+    // For each position in the window, how far back the previous occurrence of its hash is
+    prev: Vec<u32>,
+    literal_counts: [u64; 256],
+    literal_total: u64,
+    bits: f64,
+}
+
+impl Lz77Estimator {
+    pub fn new(window: usize) -> Result<Lz77Estimator, String> {
+        if !(MIN_WINDOW..=MAX_WINDOW).contains(&window) {
+            return Err(format!(
+                "Window of {} bytes is outside the supported range of {} to {} bytes",
+                window, MIN_WINDOW, MAX_WINDOW
+            ));
+        }
+        let hash_bits = window.ilog2().clamp(15, 22);
+        Ok(Lz77Estimator {
+            window,
+            buffer: Vec::new(),
+            base: 0,
+            parsed: 0,
+            hash_bits,
+            head: vec![0; 1 << hash_bits],
+            prev: Vec::new(),
+            literal_counts: [0; 256],
+            literal_total: 0,
+            bits: 0.0,
+        })
+    }
+
+    /// Parses everything written so far and returns the estimated compressed size in bytes.
+    pub fn finish(mut self) -> u64 {
+        self.parse(self.end());
+        (self.bits / 8.0).ceil() as u64
+    }
+
+    fn end(&self) -> usize {
+        self.base + self.buffer.len()
+    }
+
+    // Parses the stream up to limit. A match can run past limit, but never past what's buffered.
+    fn parse(&mut self, limit: usize) {
+        let end = self.end();
+        while self.parsed < limit {
+            let position = self.parsed;
+            let (length, distance) = if position + MIN_MATCH <= end {
+                let found = self.longest_match(position, end);
+                self.insert(position);
+                found
+            } else {
+                (0, 0)
+            };
+            if length >= MIN_MATCH {
+                self.bits += MATCH_BITS + (distance as f64).log2() + (length as f64).log2();
+                for skipped in position + 1..position + length {
+                    if skipped + MIN_MATCH <= end {
+                        self.insert(skipped);
+                    }
+                }
+                self.parsed += length;
+            } else {
+                let byte = self.byte(position) as usize;
+                // Laplace estimate, so bytes we've never seen still have a price
+                self.bits += ((self.literal_total + 256) as f64
+                    / (self.literal_counts[byte] + 1) as f64)
+                    .log2();
+                self.literal_counts[byte] += 1;
+                self.literal_total += 1;
+                self.parsed += 1;
+            }
+        }
+    }
+
+    fn byte(&self, position: usize) -> u8 {
+        self.buffer[position - self.base]
+    }
+
+    fn hash(&self, position: usize) -> usize {
+        let start = position - self.base;
+        let word = u32::from_le_bytes(self.buffer[start..start + MIN_MATCH].try_into().unwrap());
+        (word.wrapping_mul(2654435761) >> (32 - self.hash_bits)) as usize
+    }
+
+    fn insert(&mut self, position: usize) {
+        let hash = self.hash(position);
+        let latest = self.head[hash];
+        let delta = if latest != 0 && position + 1 - latest <= self.window {
+            (position + 1 - latest) as u32
+        } else {
+            0
+        };
+        let slot = position % self.window;
+        if slot >= self.prev.len() {
+            self.prev.resize(slot + 1, 0);
+        }
+        self.prev[slot] = delta;
+        self.head[hash] = position + 1;
+    }
+
+    // The longest earlier match for the bytes at position, as (length, distance)
+    fn longest_match(&self, position: usize, end: usize) -> (usize, usize) {
+        let limit = MAX_MATCH.min(end - position);
+        let current = &self.buffer[position - self.base..position - self.base + limit];
+        let mut best = (0, 0);
+        let latest = self.head[self.hash(position)];
+        if latest == 0 {
+            return best;
+        }
+        let mut candidate = latest - 1;
+        for _ in 0..MAX_CHAIN {
+            let distance = position - candidate;
+            if distance > self.window {
+                break;
+            }
+            let earlier = &self.buffer[candidate - self.base..];
+            let length = current
+                .iter()
+                .zip(earlier)
+                .take_while(|(a, b)| a == b)
+                .count();
+            if length > best.0 {
+                best = (length, distance);
+                if length == limit {
+                    break;
+                }
+            }
+            let delta = self.prev[candidate % self.window] as usize;
+            if delta == 0 || delta > candidate {
+                break;
+            }
+            candidate -= delta;
+        }
+        best
+    }
+
+    // Drops history that has fallen out of the window. Done in bulk, so the copy is amortized.
+    fn slide(&mut self) {
+        let keep_from = self.parsed.saturating_sub(self.window);
+        if keep_from - self.base > self.window {
+            self.buffer.drain(..keep_from - self.base);
+            self.base = keep_from;
+        }
+    }
+}
+
+impl Write for Lz77Estimator {
+    fn write(&mut self, data: &[u8]) -> std::io::Result<usize> {
+        self.buffer.extend_from_slice(data);
+        // Leave enough unparsed to find a full length match once more data arrives
+        let limit = self.end().saturating_sub(MAX_MATCH);
+        self.parse(limit);
+        self.slide();
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/**
+ * Estimates the compressed size, in bytes, of the contents of the files concatenated in the given
+ * order, as by a compressor that can refer back `window` bytes. Directories have no contents, so
+ * they are skipped.
+ */
+pub fn estimate_compressed_size(paths: &[PathBuf], window: usize) -> Result<u64, String> {
+    let mut estimator = Lz77Estimator::new(window)?;
+    for path in paths {
+        if path.is_dir() {
+            continue;
+        }
+        let mut file =
+            File::open(path).map_err(|e| format!("Could not open {}: {}", path.display(), e))?;
+        copy(&mut file, &mut estimator)
+            .map_err(|e| format!("Could not read {}: {}", path.display(), e))?;
+    }
+    Ok(estimator.finish())
+}
+
+/// Parses a size in bytes, with an optional K, M or G suffix (powers of 1024), as in "32K".
+pub fn parse_size(text: &str) -> Result<usize, String> {
+    let text = text.trim();
+    let digits = text.trim_end_matches(|c: char| c.is_ascii_alphabetic());
+    let multiplier = match text[digits.len()..].to_ascii_uppercase().as_str() {
+        "" | "B" => 1,
+        "K" | "KB" | "KIB" => 1 << 10,
+        "M" | "MB" | "MIB" => 1 << 20,
+        "G" | "GB" | "GIB" => 1 << 30,
+        suffix => return Err(format!("Unknown size suffix {:?}", suffix)),
+    };
+    digits
+        .parse::<usize>()
+        .map_err(|e| format!("Could not parse size {:?}: {}", text, e))?
+        .checked_mul(multiplier)
+        .ok_or_else(|| format!("Size {:?} is too large", text))
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::StdRng;
+    use rand::{RngCore, SeedableRng};
+
+    use crate::lz77::*;
+
+    fn random_bytes(count: usize, seed: u64) -> Vec<u8> {
+        let mut bytes = vec![0; count];
+        StdRng::seed_from_u64(seed).fill_bytes(&mut bytes);
+        bytes
+    }
+
+    fn estimate(parts: &[&[u8]], window: usize) -> u64 {
+        let mut estimator = Lz77Estimator::new(window).unwrap();
+        for part in parts {
+            estimator.write_all(part).unwrap();
+        }
+        estimator.finish()
+    }
+
+    #[test]
+    fn random_data_does_not_compress() {
+        let data = random_bytes(20000, 1);
+        let estimate = estimate(&[&data], GZIP_WINDOW);
+        assert!(estimate > 19900 && estimate < 20500, "{}", estimate);
+    }
+
+    #[test]
+    fn repeated_data_compresses() {
+        let data = random_bytes(1000, 2);
+        let repeated = data.repeat(20);
+        let estimate = estimate(&[&repeated], GZIP_WINDOW);
+        assert!(estimate < 1300, "{}", estimate);
+    }
+
+    #[test]
+    fn matches_need_to_be_in_the_window() {
+        let data = random_bytes(100 * 1024, 3);
+        let small = estimate(&[&data, &data], GZIP_WINDOW);
+        let large = estimate(&[&data, &data], 1 << 20);
+        assert!(small > 200 * 1024 - 1024, "{}", small);
+        assert!(large < 110 * 1024, "{}", large);
+    }
+
+    #[test]
+    fn ordering_changes_the_estimate() {
+        let a = random_bytes(20000, 4);
+        let b = random_bytes(20000, 5);
+        let mut a2 = a.clone();
+        a2[10000] ^= 1;
+        let apart = estimate(&[&a, &b, &a2], GZIP_WINDOW);
+        let together = estimate(&[&a, &a2, &b], GZIP_WINDOW);
+        assert!(together + 15000 < apart, "{} {}", together, apart);
+    }
+
+    #[test]
+    fn chunking_does_not_change_the_estimate() {
+        let data: Vec<u8> = random_bytes(3000, 6).repeat(30);
+        let whole = estimate(&[&data], GZIP_WINDOW);
+        let chunks: Vec<&[u8]> = data.chunks(777).collect();
+        assert_eq!(whole, estimate(&chunks, GZIP_WINDOW));
+    }
+
+    #[test]
+    fn sizes_are_checked() {
+        assert!(Lz77Estimator::new(10).is_err());
+        assert!(Lz77Estimator::new(MAX_WINDOW + 1).is_err());
+        assert_eq!(Ok(32 * 1024), parse_size("32K"));
+        assert_eq!(Ok(64 << 20), parse_size("64MiB"));
+        assert_eq!(Ok(1000), parse_size("1000"));
+        assert!(parse_size("12Q").is_err());
+    }
+}
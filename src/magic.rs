@@ -0,0 +1,165 @@
+// How many bytes from the start of a file the signatures below need
+pub const MAGIC_LENGTH: usize = 16;
+
+/// A file format recognized by the magic number at the start of the file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum FileFormat {
+    Gzip,
+    Bzip2,
+    Xz,
+    Zstd,
+    Lz4,
+    /// Also jar, apk, docx and the other formats built on zip
+    Zip,
+    SevenZip,
+    Rar,
+    Png,
+    Jpeg,
+    Gif,
+    Webp,
+    /// Also mov, m4a and the other formats built on the ISO base media format
+    Mp4,
+    Matroska,
+    Ogg,
+    Flac,
+    Mp3,
+    Pdf,
+    Elf,
+    Wasm,
+}
+
+// Bytes a file has at an offset from its start
+type Signature = (usize, &'static [u8]);
+
+// Each format, and its signatures. Where a format has more than one, any of them will do.
+const SIGNATURES: &[(FileFormat, &[Signature])] = &[
+    // The magic number, then deflate, the only compression method gzip defines
+    (FileFormat::Gzip, &[(0, &[0x1f, 0x8b, 0x08])]),
+    (
+        FileFormat::Xz,
+        &[(0, &[0xfd, b'7', b'z', b'X', b'Z', 0x00])],
+    ),
+    (FileFormat::Zstd, &[(0, &[0x28, 0xb5, 0x2f, 0xfd])]),
+    (FileFormat::Lz4, &[(0, &[0x04, 0x22, 0x4d, 0x18])]),
+    // A local file header, or the end of central directory record of an empty archive
+    (FileFormat::Zip, &[(0, b"PK\x03\x04"), (0, b"PK\x05\x06")]),
+    (
+        FileFormat::SevenZip,
+        &[(0, &[b'7', b'z', 0xbc, 0xaf, 0x27, 0x1c])],
+    ),
+    (FileFormat::Rar, &[(0, b"Rar!\x1a\x07")]),
+    (FileFormat::Png, &[(0, b"\x89PNG\r\n\x1a\n")]),
+    (FileFormat::Jpeg, &[(0, &[0xff, 0xd8, 0xff])]),
+    (FileFormat::Gif, &[(0, b"GIF87a"), (0, b"GIF89a")]),
+    (FileFormat::Mp4, &[(4, b"ftyp")]),
+    (FileFormat::Matroska, &[(0, &[0x1a, 0x45, 0xdf, 0xa3])]),
+    (FileFormat::Ogg, &[(0, b"OggS")]),
+    (FileFormat::Flac, &[(0, b"fLaC")]),
+    // An ID3v2 tag, with its major version
+    (
+        FileFormat::Mp3,
+        &[(0, b"ID3\x02"), (0, b"ID3\x03"), (0, b"ID3\x04")],
+    ),
+    (FileFormat::Pdf, &[(0, b"%PDF-")]),
+    (FileFormat::Elf, &[(0, b"\x7fELF")]),
+    (FileFormat::Wasm, &[(0, b"\0asm")]),
+];
+
+impl FileFormat {
+    /**
+     * Recognizes a file by its first bytes, which should be the first MAGIC_LENGTH bytes of the
+     * file, or all of it if it's shorter. Byte frequencies can't tell a small compressed file
+     * from a structured binary, but the header can.
+     */
+    pub fn detect(header: &[u8]) -> Option<FileFormat> {
+        // WebP is a RIFF container, which on its own could hold anything, like a wav file
+        if header.starts_with(b"RIFF") && header.get(8..12) == Some(b"WEBP") {
+            return Some(FileFormat::Webp);
+        }
+        // "BZh" is short enough to start a text file, so it has to be followed by the block size,
+        // then the magic number of the first block, or of the end of the stream if it's empty
+        if header.starts_with(b"BZh")
+            && header
+                .get(3)
+                .is_some_and(|size| (b'1'..=b'9').contains(size))
+            && matches!(header.get(4..10), Some(b"1AY&SY") | Some(b"\x17rE8P\x90"))
+        {
+            return Some(FileFormat::Bzip2);
+        }
+        SIGNATURES
+            .iter()
+            .find(|(_, signatures)| {
+                signatures.iter().any(|(offset, magic)| {
+                    header.get(*offset..offset + magic.len()) == Some(*magic)
+                })
+            })
+            .map(|(format, _)| *format)
+    }
+
+    /// Whether the contents are already compressed, so the file won't compress any further and
+    /// isn't much like any other file. Pdf streams usually are compressed, but the text around
+    /// them isn't, and executables aren't. Only a pdf's %PDF- header is recognized, so a pdf is
+    /// never treated as compressed, whatever its streams hold.
+    pub fn is_compressed(&self) -> bool {
+        !matches!(self, Self::Pdf | Self::Elf | Self::Wasm)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::magic::*;
+
+    #[test]
+    fn formats_are_detected_from_headers() {
+        assert_eq!(
+            Some(FileFormat::Gzip),
+            FileFormat::detect(&[0x1f, 0x8b, 8, 0])
+        );
+        assert_eq!(
+            Some(FileFormat::Png),
+            FileFormat::detect(b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR")
+        );
+        assert_eq!(
+            Some(FileFormat::Mp4),
+            FileFormat::detect(b"\0\0\0\x20ftypisom")
+        );
+        assert_eq!(
+            Some(FileFormat::Webp),
+            FileFormat::detect(b"RIFF\x24\0\0\0WEBPVP8 ")
+        );
+        assert_eq!(None, FileFormat::detect(b"RIFF\x24\0\0\0WAVEfmt "));
+        assert_eq!(Some(FileFormat::Zip), FileFormat::detect(b"PK\x05\x06"));
+        assert_eq!(Some(FileFormat::Pdf), FileFormat::detect(b"%PDF-1.7\n"));
+        assert_eq!(None, FileFormat::detect(&[0x1f, 0x8b, 0x09]));
+    }
+
+    #[test]
+    fn short_signatures_need_what_follows_them() {
+        assert_eq!(
+            Some(FileFormat::Bzip2),
+            FileFormat::detect(b"BZh91AY&SY\x8f\x1a")
+        );
+        assert_eq!(
+            Some(FileFormat::Bzip2),
+            FileFormat::detect(b"BZh9\x17rE8P\x90\0\0\0\0")
+        );
+        assert_eq!(None, FileFormat::detect(b"BZh0 is a text file"));
+        assert_eq!(None, FileFormat::detect(b"BZh9 is a text file"));
+        assert_eq!(None, FileFormat::detect(b"BZh"));
+        assert_eq!(
+            Some(FileFormat::Mp3),
+            FileFormat::detect(b"ID3\x03\0\0\0\0\x1fv")
+        );
+        assert_eq!(None, FileFormat::detect(b"ID3 tags are metadata"));
+        assert_eq!(None, FileFormat::detect(b"plain text"));
+        assert_eq!(None, FileFormat::detect(b""));
+    }
+
+    #[test]
+    fn only_some_formats_are_compressed() {
+        assert!(FileFormat::Jpeg.is_compressed());
+        assert!(FileFormat::Zstd.is_compressed());
+        assert!(!FileFormat::Pdf.is_compressed());
+        assert!(!FileFormat::Elf.is_compressed());
+    }
+}
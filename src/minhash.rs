@@ -6,8 +6,50 @@ use std::path::PathBuf;
 
 use crc32fast;
 
-const SHINGLE_SIZE: u8 = 8;
-const FEATURE_COUNT: u8 = 128;
+use crate::magic::{FileFormat, MAGIC_LENGTH};
+
+pub const SHINGLE_SIZE: u8 = 8;
+pub const FEATURE_COUNT: u8 = 128;
+
+/// How files are sketched: the length of the byte strings hashed, and how many of the smallest
+/// hashes are kept. Only signatures made with the same settings can be compared.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MinhashSettings {
+    pub shingle_size: u8,
+    pub feature_count: u8,
+}
+
+impl Default for MinhashSettings {
+    fn default() -> MinhashSettings {
+        MinhashSettings {
+            shingle_size: SHINGLE_SIZE,
+            feature_count: FEATURE_COUNT,
+        }
+    }
+}
+
+/// Statistics of the bytes of a file, gathered in the same pass that hashes it.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ByteStatistics {
+    /// Order-0 entropy, in bits per byte
+    pub entropy: f64,
+    /// Entropy of each byte given the byte before it, in bits per byte. This is closer to what a
+    /// compressor sees, but it's underestimated for small files, where most pairs of bytes are
+    /// only seen once.
+    pub conditional_entropy: f64,
+    /// Fraction of the bytes that are printable ascii or whitespace
+    pub printable: f64,
+}
+
+impl ByteStatistics {
+    /// How differently two files look to a compressor, from 0 for files with the same entropies
+    /// to 1 for a file of a single repeated byte against random data.
+    pub fn entropy_distance(&self, other: &ByteStatistics) -> f64 {
+        ((self.entropy - other.entropy).abs()
+            + (self.conditional_entropy - other.conditional_entropy).abs())
+            / 16.0
+    }
+}
 
 /**
  * This module is based on the code in binsort, but changes the name from simhash to minhash,
@@ -21,15 +63,23 @@ pub struct Minhash {
     shingle_size: u8,
     // These values are not in the binsort implementation, they're my addition
     pub byte_distribution: ByteDistribution,
+    // The format the file's magic number says it's in, if it has one we know
+    pub format: Option<FileFormat>,
+    pub statistics: ByteStatistics,
+    // Number of shingles hashed, used as a stand-in for the size of the shingle set
+    shingle_count: u64,
 }
 
 impl Minhash {
-    fn new() -> Minhash {
+    fn with_settings(settings: MinhashSettings) -> Minhash {
         return Minhash {
             features: Vec::new(),
-            feature_count: FEATURE_COUNT,
-            shingle_size: SHINGLE_SIZE,
+            feature_count: settings.feature_count,
+            shingle_size: settings.shingle_size,
             byte_distribution: ByteDistribution::Uniform,
+            format: None,
+            statistics: ByteStatistics::default(),
+            shingle_count: 0,
         };
     }
 
@@ -72,18 +122,112 @@ impl Minhash {
         let unionsize = 2 * count - matchcount;
         return (matchcount as f64) / (unionsize as f64);
     }
+
+    // The smallest hashes of the file's shingles, in ascending order
+    pub fn features(&self) -> &[u32] {
+        &self.features
+    }
+
+    // The standard bottom-k estimate: of the k smallest hashes in the union of both sketches, the
+    // fraction present in both. score divides by the size of the combined sketches instead, which
+    // underestimates similarity when the files differ in size.
+    fn bottom_k_jaccard(&self, other: &Minhash) -> f64 {
+        let k = std::cmp::min(self.features.len(), other.features.len());
+        if k == 0 {
+            return 0.0;
+        }
+        let mut i1 = 0;
+        let mut i2 = 0;
+        let mut matchcount = 0;
+        for _ in 0..k {
+            let f1 = self.features[i1];
+            let f2 = other.features[i2];
+            if f1 == f2 {
+                matchcount += 1;
+                i1 += 1;
+                i2 += 1;
+            } else if f1 < f2 {
+                i1 += 1;
+            } else {
+                i2 += 1;
+            }
+            if i1 == self.features.len() || i2 == other.features.len() {
+                break;
+            }
+        }
+        (matchcount as f64) / (k as f64)
+    }
+
+    /**
+     * Estimates the fraction of this file's shingles that also appear in `container`. Unlike score,
+     * this is not symmetric: a small file copied into a large one is fully contained in it, but
+     * covers only a small part of it.
+     *
+     * With set sizes |A| and |B| and jaccard similarity J, |A ∩ B| = J(|A| + |B|) / (1 + J). We
+     * don't track the number of distinct shingles, so the shingle counts are used as the sizes.
+     */
+    pub fn containment(&self, container: &Minhash) -> f64 {
+        if self.shingle_count == 0 {
+            return 0.0;
+        }
+        let jaccard = self.bottom_k_jaccard(container);
+        let sizes = (self.shingle_count + container.shingle_count) as f64;
+        let intersection = jaccard * sizes / (1.0 + jaccard);
+        (intersection / self.shingle_count as f64).min(1.0)
+    }
+}
+
+// Maps a similarity score in [0, 1] to a distance, with identical files at distance 0
+pub fn similarity_to_distance(similarity: f64) -> u8 {
+    255 - ((similarity * 255.0).floor() as u8)
+}
+
+// Adds the --entropy-weight term to a distance, so that files that look alike to minhash but
+// compress very differently end up further apart
+pub fn add_entropy_term(
+    distance: u8,
+    first: &Minhash,
+    second: &Minhash,
+    entropy_weight: f64,
+) -> u8 {
+    if entropy_weight == 0.0 {
+        return distance;
+    }
+    let term = entropy_weight * first.statistics.entropy_distance(&second.statistics) * 255.0;
+    (distance as f64 + term).min(255.0) as u8
 }
 
 pub fn minhash_stream(target: &MinhashTarget) -> Result<Minhash, Error> {
+    minhash_stream_with(target, MinhashSettings::default())
+}
+
+pub fn minhash_stream_with(
+    target: &MinhashTarget,
+    settings: MinhashSettings,
+) -> Result<Minhash, Error> {
+    minhash_target(target, settings, true)
+}
 
-    let mut minhash = Minhash::new();
+// Without the path, a file gets the same signature wherever it is, which tests rely on since
+// temporary file names are random
+pub(crate) fn minhash_target(
+    target: &MinhashTarget,
+    settings: MinhashSettings,
+    hash_path: bool,
+) -> Result<Minhash, Error> {
+    let mut minhash = Minhash::with_settings(settings);
     let mut heap: BinaryHeap<u32> = BinaryHeap::new();
     let mut buf = Vec::new();
     let mut filled_buf: bool = false;
     let path = target.get_path();
 
     let mut filename_byte_distribution = ByteCount::new();
-    for &b in path.as_os_str().as_encoded_bytes() {
+    let path_bytes: &[u8] = if hash_path {
+        path.as_os_str().as_encoded_bytes()
+    } else {
+        &[]
+    };
+    for &b in path_bytes {
         filename_byte_distribution.record_byte(b);
         if !filled_buf {
             buf.push(b);
@@ -103,6 +247,7 @@ pub fn minhash_stream(target: &MinhashTarget) -> Result<Minhash, Error> {
     // the idea is that not doing that would make many files show up as non-random.
     // It might actually be correct to do that--a small binary file would get treated as
     // non-uniform, while a larger one would be uniform, but that's an improvement for later.
+    minhash.statistics = filename_byte_distribution.statistics();
     minhash.byte_distribution = filename_byte_distribution.to_distribution();
 
     match target {
@@ -121,12 +266,17 @@ fn shingle_file(minhash: &mut Minhash, heap: &mut BinaryHeap<u32>, mut buf: Vec<
     let f = File::open(path)?;
     let mut reader = BufReader::new(f);
     let mut buffer = [0; 1024];
-    let mut byte_count = ByteCount::new();
+    let mut byte_count = ByteCount::with_pairs();
+    let mut header = Vec::with_capacity(MAGIC_LENGTH);
     loop {
         let n = reader.read(&mut buffer)?;
         if n == 0 {
             break;
         }
+        if header.len() < MAGIC_LENGTH {
+            let missing = (MAGIC_LENGTH - header.len()).min(n);
+            header.extend_from_slice(&buffer[..missing]);
+        }
         for &b in &buffer[..n] {
             byte_count.record_byte(b);
             if !filled_buf {
@@ -145,6 +295,8 @@ fn shingle_file(minhash: &mut Minhash, heap: &mut BinaryHeap<u32>, mut buf: Vec<
             shingle_update(minhash, heap, hash);
         }
     }
+    minhash.format = FileFormat::detect(&header);
+    minhash.statistics = byte_count.statistics();
     Ok(byte_count)
 }
 
@@ -160,6 +312,7 @@ fn push_back(buf: &mut Vec<u8>, byte: u8) {
 
 fn shingle_update(minhash: &mut Minhash, heap: &mut BinaryHeap<u32>, hash: u32) {
     let mut hashes = HashSet::new();
+    minhash.shingle_count += 1;
 
     match heap.peek() {
         Some(&h) => {
@@ -182,9 +335,36 @@ fn shingle_update(minhash: &mut Minhash, heap: &mut BinaryHeap<u32>, hash: u32)
     }
 }
 
+// Counts of each byte following each other byte, with a row for each byte that came before
+// another. Rows are only allocated for the bytes a file has, since setting up and scanning a table
+// of all 64K pairs takes longer than hashing a small file.
+struct PairCount {
+    rows: Vec<Option<Box<[u32; 256]>>>,
+}
+
+impl PairCount {
+    fn new() -> PairCount {
+        PairCount {
+            rows: vec![None; 256],
+        }
+    }
+
+    fn record(&mut self, previous: u8, byte: u8) {
+        let row = self.rows[previous as usize].get_or_insert_with(|| Box::new([0; 256]));
+        row[byte as usize] += 1;
+    }
+
+    fn rows(&self) -> impl Iterator<Item = &[u32; 256]> + Clone {
+        self.rows.iter().flatten().map(|row| &**row)
+    }
+}
+
 struct ByteCount {
     count: u64,
     bytes: Vec<u32>,
+    // Only counted for file contents
+    pairs: Option<PairCount>,
+    previous: Option<u8>,
 }
 
 impl ByteCount {
@@ -192,12 +372,57 @@ impl ByteCount {
         ByteCount {
             count: 0,
             bytes: vec![0; 256],
+            pairs: None,
+            previous: None,
+        }
+    }
+
+    fn with_pairs() -> ByteCount {
+        ByteCount {
+            pairs: Some(PairCount::new()),
+            ..ByteCount::new()
         }
     }
 
     fn record_byte(&mut self, byte: u8) {
         self.bytes[byte as usize] += 1;
         self.count += 1;
+        if let (Some(pairs), Some(previous)) = (&mut self.pairs, self.previous) {
+            pairs.record(previous, byte);
+        }
+        self.previous = Some(byte);
+    }
+
+    fn statistics(&self) -> ByteStatistics {
+        if self.count == 0 {
+            return ByteStatistics::default();
+        }
+        let entropy = shannon_entropy(self.bytes.iter().map(|&c| c as u64), self.count);
+        // H(X | previous X) = H(previous X, X) - H(previous X)
+        let conditional_entropy = match &self.pairs {
+            Some(pairs) if self.count > 1 => {
+                let counts = pairs.rows().flat_map(|row| row.iter().map(|&c| c as u64));
+                let joint = shannon_entropy(counts, self.count - 1);
+                let previous = pairs.rows().map(|row| row.iter().map(|&c| c as u64).sum());
+                (joint - shannon_entropy(previous, self.count - 1)).max(0.0)
+            }
+            _ => entropy,
+        };
+        let printable: u64 = self
+            .bytes
+            .iter()
+            .enumerate()
+            .filter(|(byte, _)| {
+                let byte = *byte as u8;
+                byte.is_ascii_graphic() || matches!(byte, b' ' | b'\t' | b'\n' | b'\r')
+            })
+            .map(|(_, &count)| count as u64)
+            .sum();
+        ByteStatistics {
+            entropy,
+            conditional_entropy,
+            printable: printable as f64 / self.count as f64,
+        }
     }
 
     fn ascii(&self) -> bool {
@@ -251,6 +476,17 @@ impl ByteCount {
     }
 }
 
+// Shannon entropy, in bits, of the distribution with the given counts
+fn shannon_entropy<I: Iterator<Item = u64>>(counts: I, total: u64) -> f64 {
+    counts
+        .filter(|&count| count > 0)
+        .map(|count| {
+            let p = count as f64 / total as f64;
+            -p * p.log2()
+        })
+        .sum()
+}
+
 #[derive(Debug)]
 pub enum ByteDistribution {
     // Represents a set of bytes that match a uniform distribution for frequency. Does not test for randomness. [1, 2, 3, 4, 5, 6...] would count as uniform.
@@ -296,13 +532,44 @@ mod tests {
         let mut file = NamedTempFile::new().unwrap();
         file.write_all(bytes).unwrap();
         let temp_path = file.into_temp_path();
-        let mut minhash = Minhash::new();
+        let mut minhash = Minhash::with_settings(MinhashSettings::default());
         let mut heap = BinaryHeap::new();
         shingle_file(&mut minhash, &mut heap, Vec::new(), false, &temp_path.to_path_buf()).unwrap();
         minhash.features = heap.into_sorted_vec();
         minhash
     }
 
+    #[test]
+    fn statistics_measure_entropy() {
+        let alternating = shingle_bytes(&b"ab".repeat(1000)).statistics;
+        assert!((alternating.entropy - 1.0).abs() < 1e-9);
+        assert!(alternating.conditional_entropy < 1e-9);
+        assert_eq!(1.0, alternating.printable);
+
+        let mut rng = StdRng::seed_from_u64(50);
+        let random: Vec<u8> = (0..100000).map(|_| rng.gen()).collect();
+        let random = shingle_bytes(&random).statistics;
+        assert!(random.entropy > 7.99, "{:?}", random);
+        assert!(random.conditional_entropy > 7.0, "{:?}", random);
+        assert!(random.printable > 0.35 && random.printable < 0.40);
+
+        let zeros = shingle_bytes(&vec![0; 1000]).statistics;
+        assert_eq!(0.0, zeros.entropy);
+        assert_eq!(0.0, zeros.printable);
+        assert!((zeros.entropy_distance(&random) - 1.0).abs() < 0.1);
+    }
+
+    #[test]
+    fn magic_numbers_set_the_format() {
+        let mut bytes = vec![0x28, 0xb5, 0x2f, 0xfd];
+        bytes.extend_from_slice(b"the rest of a zstd frame");
+        assert_eq!(Some(FileFormat::Zstd), shingle_bytes(&bytes).format);
+        assert_eq!(
+            None,
+            shingle_bytes(&b"the rest of a zstd frame".to_vec()).format
+        );
+    }
+
     #[test]
     fn lengthening_string_adds_shingle_features() {
         let mut buf =
@@ -376,6 +643,19 @@ mod tests {
         assert!(cmp < 1.0);
     }
 
+    #[test]
+    fn containment_is_asymmetric() {
+        let mut rng = StdRng::seed_from_u64(7);
+        let small: Vec<u8> = (0..512).map(|_| rng.gen()).collect();
+        let mut large = small.clone();
+        large.extend((0..8192).map(|_| rng.gen::<u8>()));
+        let small_minhash = shingle_bytes(&small);
+        let large_minhash = shingle_bytes(&large);
+        assert!(small_minhash.containment(&large_minhash) > 0.5);
+        assert!(large_minhash.containment(&small_minhash) < 0.2);
+        assert_eq!(1.0, small_minhash.containment(&small_minhash));
+    }
+
     #[test]
     fn disjoint_strings_compare_as_zero() {
         let buf1 = "a".repeat(128).as_bytes().to_vec();
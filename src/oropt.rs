@@ -0,0 +1,214 @@
+use std::ops::Range;
+
+use crate::tour::positions;
+use crate::tsp::TspInstance;
+
+// Or-opt moves segments of at most this many nodes
+const OR_OPT_SEGMENT: usize = 3;
+
+/// What Or-opt needs from an instance: the cost of each directed edge, and the nodes worth trying
+/// right before or right after a node, where segments might be inserted.
+pub trait OrOptInstance {
+    fn edge_cost(&self, from: usize, to: usize) -> u64;
+
+    fn predecessors(&self, node: usize) -> &[usize];
+
+    fn successors(&self, node: usize) -> &[usize];
+}
+
+impl<I: TspInstance> OrOptInstance for I {
+    fn edge_cost(&self, from: usize, to: usize) -> u64 {
+        self.retrieve_distance(from, to)
+    }
+
+    fn predecessors(&self, node: usize) -> &[usize] {
+        self.neighbors(node)
+    }
+
+    fn successors(&self, node: usize) -> &[usize] {
+        self.neighbors(node)
+    }
+}
+
+/// Makes one pass of Or-opt over an open path: each segment of up to OR_OPT_SEGMENT nodes is moved
+/// between two other adjacent nodes if that shortens the path. If `reversible`, the segment may
+/// also be inserted backwards, whichever is cheaper; asymmetric instances keep it as it is, since
+/// reversing it would change the cost of every edge inside it. Insertion points are taken from
+/// the neighbor lists of the segment's end nodes. Pinned ends of the path never move. Returns
+/// whether the path was improved.
+pub fn or_opt_path<I: OrOptInstance>(
+    tsp: &I,
+    path: &mut [usize],
+    pin_start: bool,
+    pin_end: bool,
+    reversible: bool,
+) -> bool {
+    let count = path.len();
+    if count < 3 {
+        return false;
+    }
+    let mut improved = false;
+    let mut positions = positions(path);
+    for segment_length in 1..=OR_OPT_SEGMENT.min(count - 2) {
+        let mut s = 0;
+        while s + segment_length <= count {
+            let t = s + segment_length - 1;
+            if (pin_start && s == 0) || (pin_end && t == count - 1) {
+                s += 1;
+                continue;
+            }
+            let first = path[s];
+            let last = path[t];
+            let prev = if s > 0 { Some(path[s - 1]) } else { None };
+            let next = path.get(t + 1).copied();
+            let removal_gain =
+                edge(tsp, prev, Some(first)) + edge(tsp, Some(last), next) - edge(tsp, prev, next);
+            if removal_gain <= 0 {
+                s += 1;
+                continue;
+            }
+
+            // Each candidate is an insertion point (x, y), with the segment landing between them.
+            // x should come right before the segment's first node, and y right after its last.
+            let before = |node: usize| positions[node].checked_sub(1).map(|p| path[p]);
+            let after = |node: usize| path.get(positions[node] + 1).copied();
+            let mut ends = vec![(first, last)];
+            if reversible {
+                ends.push((last, first));
+            }
+            let mut candidates = Vec::new();
+            for &(head, tail) in &ends {
+                for &x in tsp.predecessors(head) {
+                    candidates.push((Some(x), after(x)));
+                }
+                for &y in tsp.successors(tail) {
+                    candidates.push((before(y), Some(y)));
+                }
+            }
+            if !pin_start {
+                candidates.push((None, Some(path[0])));
+            }
+            if !pin_end {
+                candidates.push((Some(path[count - 1]), None));
+            }
+
+            let in_segment = |node: Option<usize>| match node {
+                Some(n) => positions[n] >= s && positions[n] <= t,
+                None => false,
+            };
+            let best = candidates
+                .into_iter()
+                .filter(|&(x, y)| !in_segment(x) && !in_segment(y) && (x, y) != (prev, next))
+                .filter(|&(x, y)| (x.is_some() || !pin_start) && (y.is_some() || !pin_end))
+                .flat_map(|(x, y)| {
+                    let existing = edge(tsp, x, y);
+                    let forward = edge(tsp, x, Some(first)) + edge(tsp, Some(last), y) - existing;
+                    let reversed = edge(tsp, x, Some(last)) + edge(tsp, Some(first), y) - existing;
+                    [
+                        Some((forward, x, false)),
+                        reversible.then_some((reversed, x, true)),
+                    ]
+                })
+                .flatten()
+                .min_by_key(|&(cost, _, _)| cost);
+            if let Some((insertion_cost, x, reverse)) = best {
+                if insertion_cost < removal_gain {
+                    move_segment(path, &mut positions, s..t + 1, x, reverse);
+                    improved = true;
+                }
+            }
+            s += 1;
+        }
+    }
+    improved
+}
+
+// Moves a segment of the path to just after x, or to the start of the path for None, reversing
+// it if asked. Only the nodes between its old and new places shift, so only their positions are
+// updated, rather than every node's.
+fn move_segment(
+    path: &mut [usize],
+    positions: &mut [usize],
+    segment: Range<usize>,
+    x: Option<usize>,
+    reverse: bool,
+) {
+    let length = segment.len();
+    let (shifted, start) = match x.map(|x| positions[x]) {
+        Some(position) if position >= segment.end => {
+            path[segment.start..=position].rotate_left(length);
+            (segment.start..position + 1, position + 1 - length)
+        }
+        position => {
+            let start = position.map_or(0, |p| p + 1);
+            path[start..segment.end].rotate_right(length);
+            (start..segment.end, start)
+        }
+    };
+    if reverse {
+        path[start..start + length].reverse();
+    }
+    for p in shifted {
+        positions[path[p]] = p;
+    }
+}
+
+// Distance between two positions on a path, where None stands for the open end of the path
+fn edge<I: OrOptInstance>(tsp: &I, from: Option<usize>, to: Option<usize>) -> i64 {
+    match (from, to) {
+        (Some(from), Some(to)) => tsp.edge_cost(from, to) as i64,
+        _ => 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::oropt::*;
+    use crate::testutils::*;
+    use crate::tsp::Tsp;
+
+    #[test]
+    fn or_opt_moves_misplaced_node() {
+        let tsp = Tsp::new(build_linear_distances(10), 10);
+        let mut path = vec![0, 1, 2, 7, 3, 4, 5, 6, 8, 9];
+        assert!(or_opt_path(&tsp, &mut path, false, false, true));
+        check_permutation(&path, 9);
+        assert_eq!(9, tsp.calculate_distance(&path));
+    }
+
+    #[test]
+    fn or_opt_reverses_moved_segment() {
+        let tsp = Tsp::new(build_linear_distances(10), 10);
+        let mut path = vec![0, 1, 2, 6, 5, 7, 8, 9, 4, 3];
+        let distance = tsp.calculate_distance(&path);
+        while or_opt_path(&tsp, &mut path, false, false, true) {}
+        check_permutation(&path, 9);
+        assert!(tsp.calculate_distance(&path) < distance);
+    }
+
+    #[test]
+    fn or_opt_keeps_pinned_ends() {
+        let tsp = Tsp::new(build_linear_distances(10), 10);
+        let mut path = vec![5, 1, 2, 3, 4, 0, 6, 7, 8, 9];
+        while or_opt_path(&tsp, &mut path, true, true, true) {}
+        check_permutation(&path, 9);
+        assert_eq!(5, path[0]);
+        assert_eq!(9, path[9]);
+    }
+
+    #[test]
+    fn moved_segments_keep_positions_in_sync() {
+        for (segment, x, reverse, expected) in [
+            (2..4, Some(6), false, vec![0, 1, 4, 5, 6, 2, 3, 7]),
+            (4..7, Some(1), true, vec![0, 1, 6, 5, 4, 2, 3, 7]),
+            (5..8, None, false, vec![5, 6, 7, 0, 1, 2, 3, 4]),
+            (0..1, Some(7), false, vec![1, 2, 3, 4, 5, 6, 7, 0]),
+        ] {
+            let mut path: Vec<usize> = (0..8).collect();
+            let mut positions = positions(&path);
+            move_segment(&mut path, &mut positions, segment, x, reverse);
+            assert_eq!(expected, path);
+            assert_eq!(crate::tour::positions(&path), positions);
+        }
+    }
+}
@@ -0,0 +1,147 @@
+use clap::ValueEnum;
+
+use crate::compress::{Compressor, CompressorSetting};
+use crate::lz77::{GZIP_WINDOW, MAX_WINDOW};
+use crate::minhash::{MinhashSettings, FEATURE_COUNT, SHINGLE_SIZE};
+use crate::window::WINDOW_FILE_LIMIT;
+
+/// The compressor the archive is meant for. Each one sees the stream differently, so each gets
+/// its own defaults for how files are compared and ordered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Profile {
+    /// 32K window, so only neighboring files help each other
+    Gzip,
+    /// Compresses independent 900K blocks, with no matches between them
+    Bzip2,
+    /// 8M dictionary at the default level
+    Xz,
+    /// 2M window at the default level
+    Zstd,
+    /// zstd --long, with a 128M window
+    ZstdLong,
+    /// 4M window at the default quality
+    Brotli,
+}
+
+/// The defaults a profile sets. Command line options override them one by one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProfileSettings {
+    pub minhash: MinhashSettings,
+    /// How far back, in bytes, the compressor can find a match
+    pub window: usize,
+    /// Whether to optimize for every file in the window, rather than just the previous file
+    pub window_objective: bool,
+    /// Upper limit on the batches tsp and sparse-tsp optimize for the window objective, on top of
+    /// the limit of the algorithm
+    pub max_batch: usize,
+    /// Whether incompressible files go at the end of the archive, rather than the start
+    pub uniform_last: bool,
+    /// The compressor to run for trial compressions, if it's one we know how to run
+    pub trial: Option<CompressorSetting>,
+}
+
+impl Profile {
+    pub fn settings(&self) -> ProfileSettings {
+        let defaults = ProfileSettings {
+            minhash: MinhashSettings::default(),
+            window: GZIP_WINDOW,
+            window_objective: false,
+            // Tour length is cheap to optimize at any size, so gzip and bzip2 batches are only
+            // limited by the algorithm
+            max_batch: usize::MAX,
+            uniform_last: false,
+            trial: None,
+        };
+        let trial = |compressor, level| Some(CompressorSetting { compressor, level });
+        match self {
+            // The settings simsort has always used
+            Self::Gzip => ProfileSettings {
+                trial: trial(Compressor::Gzip, 6),
+                ..defaults
+            },
+            // The Burrows-Wheeler transform groups similar short contexts from anywhere in a
+            // block, so short strings are what files need to share. Blocks start every 900K of
+            // input wherever the files fall, which a sliding window doesn't model, so this keeps
+            // the path objective: similar files next to each other usually share a block. The
+            // window only sizes the LZ77 estimates, as a rough stand-in for a block.
+            Self::Bzip2 => ProfileSettings {
+                minhash: MinhashSettings {
+                    shingle_size: 4,
+                    feature_count: FEATURE_COUNT / 2,
+                },
+                window: 900 * 1000,
+                trial: trial(Compressor::Bzip2, 9),
+                ..defaults
+            },
+            // LZMA's range coder adapts its probabilities as it goes, so starting the stream with
+            // incompressible data trains it on the wrong thing. Like the other profiles with a
+            // long window, batches are kept small enough that the window objective refines them.
+            Self::Xz => ProfileSettings {
+                window: 8 << 20,
+                window_objective: true,
+                max_batch: WINDOW_FILE_LIMIT,
+                uniform_last: true,
+                trial: trial(Compressor::Xz, 6),
+                ..defaults
+            },
+            // zstd's shortest matches are 5 or 6 bytes at the faster levels
+            Self::Zstd => ProfileSettings {
+                minhash: MinhashSettings {
+                    shingle_size: 6,
+                    feature_count: FEATURE_COUNT,
+                },
+                window: 2 << 20,
+                window_objective: true,
+                max_batch: WINDOW_FILE_LIMIT,
+                trial: trial(Compressor::Zstd, 3),
+                ..defaults
+            },
+            // The window is 128M, but we simulate at most MAX_WINDOW
+            Self::ZstdLong => ProfileSettings {
+                minhash: MinhashSettings {
+                    shingle_size: SHINGLE_SIZE,
+                    feature_count: FEATURE_COUNT,
+                },
+                window: MAX_WINDOW,
+                window_objective: true,
+                max_batch: WINDOW_FILE_LIMIT,
+                ..defaults
+            },
+            Self::Brotli => ProfileSettings {
+                minhash: MinhashSettings {
+                    shingle_size: 6,
+                    feature_count: FEATURE_COUNT,
+                },
+                window: 4 << 20,
+                window_objective: true,
+                max_batch: WINDOW_FILE_LIMIT,
+                ..defaults
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::profile::*;
+
+    #[test]
+    fn gzip_profile_keeps_the_old_defaults() {
+        let settings = Profile::Gzip.settings();
+        assert_eq!(MinhashSettings::default(), settings.minhash);
+        assert_eq!(GZIP_WINDOW, settings.window);
+        assert!(!settings.window_objective);
+        assert_eq!(usize::MAX, settings.max_batch);
+        assert!(!settings.uniform_last);
+    }
+
+    #[test]
+    fn profile_windows_are_in_range() {
+        for profile in Profile::value_variants() {
+            let settings = profile.settings();
+            assert!(settings.window >= GZIP_WINDOW && settings.window <= MAX_WINDOW);
+            assert!(settings.minhash.shingle_size > 0 && settings.minhash.feature_count > 0);
+            assert!(!settings.window_objective || settings.max_batch <= WINDOW_FILE_LIMIT);
+        }
+    }
+}
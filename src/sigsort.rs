@@ -0,0 +1,117 @@
+use crate::distances::TriangularDistances;
+use crate::minhash::{similarity_to_distance, Minhash};
+use crate::tour::Tour;
+use crate::tsp::Tsp;
+use crate::twoopt::{optimize_twoopt_path, MINIMUM_ITEMS};
+
+// How many of the smallest features make up the sort key. Files that share their smallest
+// features share a large part of their content, fewer features make groups coarser.
+const PROJECTION_SIZE: usize = 4;
+
+// Local search runs over windows of this many files, overlapping by half a window
+pub const DEFAULT_WINDOW: usize = 64;
+
+/**
+ * Orders files in O(n log n) by sorting them on a compact projection of their signature, then
+ * cleans up the order with 2-opt inside a sliding window. This scales to trees far too large for
+ * the tsp algorithms, while still keeping files with shared content together.
+ *
+ * The projection is the smallest few minhash features. Those are the features most likely to be
+ * shared by similar files, so sorting on them lexicographically groups files by content.
+ */
+pub fn signature_sort(signatures: &[Minhash], window: usize) -> Result<Vec<usize>, String> {
+    let mut order: Vec<usize> = (0..signatures.len()).collect();
+    order.sort_by_cached_key(|&i| {
+        let features = signatures[i].features();
+        features[..PROJECTION_SIZE.min(features.len())].to_vec()
+    });
+    windowed_twoopt(signatures, &mut order, window)?;
+    Ok(order)
+}
+
+/// Runs 2-opt over each window of the order. Windows overlap, and the files at the edges of a
+/// window stay in place, so each window stays joined to the rest of the order.
+fn windowed_twoopt(
+    signatures: &[Minhash],
+    order: &mut [usize],
+    window: usize,
+) -> Result<(), String> {
+    let count = order.len();
+    let window = window.max(MINIMUM_ITEMS + 1);
+    let mut start = 0;
+    while start + MINIMUM_ITEMS <= count {
+        let end = (start + window).min(count);
+        let members = &order[start..end];
+        let mut distances = TriangularDistances::packed(members.len());
+        for i in 0..members.len() {
+            for j in i..members.len() {
+                let similarity = signatures[members[i]].score(&signatures[members[j]]);
+                distances.set(i, j, similarity_to_distance(similarity));
+            }
+        }
+        let tsp = Tsp::from_distances(distances);
+        let first = if start > 0 { Some(0) } else { None };
+        let last = if end < count {
+            Some(members.len() - 1)
+        } else {
+            None
+        };
+        let local =
+            optimize_twoopt_path(&tsp, Tour::new((0..members.len()).collect()), first, last)?;
+        let reordered: Vec<usize> = local.into_iter().map(|i| members[i]).collect();
+        order[start..end].copy_from_slice(&reordered);
+        if end == count {
+            break;
+        }
+        start += window / 2;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::sigsort::*;
+    use crate::testutils::*;
+
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    // Interleaved groups of small edits of a shared random base
+    fn build_signatures(groups: usize, per_group: usize) -> Vec<Minhash> {
+        let mut rng = StdRng::seed_from_u64(35);
+        let bases: Vec<Vec<u8>> = (0..groups)
+            .map(|_| (0..2048).map(|_| rng.gen()).collect())
+            .collect();
+        let mut signatures = Vec::new();
+        for _ in 0..per_group {
+            for base in &bases {
+                let mut bytes = base.clone();
+                let position = rng.gen_range(0..bytes.len());
+                bytes[position] = rng.gen();
+                signatures.push(minhash_bytes(&bytes));
+            }
+        }
+        signatures
+    }
+
+    #[test]
+    fn signature_sort_groups_similar_files() {
+        let signatures = build_signatures(5, 6);
+        let order = signature_sort(&signatures, 8).unwrap();
+        check_permutation(&order, 29);
+        // Each group is one run, so there are only 4 places where the group changes
+        let changes = order
+            .windows(2)
+            .filter(|pair| pair[0] % 5 != pair[1] % 5)
+            .count();
+        assert_eq!(4, changes, "{:?}", order);
+    }
+
+    #[test]
+    fn signature_sort_handles_tiny_inputs() {
+        let signatures = build_signatures(1, 2);
+        let order = signature_sort(&signatures, DEFAULT_WINDOW).unwrap();
+        check_permutation(&order, 1);
+        assert!(signature_sort(&[], DEFAULT_WINDOW).unwrap().is_empty());
+    }
+}
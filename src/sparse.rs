@@ -0,0 +1,207 @@
+use std::collections::BinaryHeap;
+
+use crate::minhash::{add_entropy_term, similarity_to_distance, Minhash};
+use crate::tsp::TspInstance;
+
+/// A tsp instance that only stores the nearest neighbors of each node, along with their distances.
+/// Any other distance is computed from the file signatures when it's needed. Memory use is linear
+/// in the number of files, rather than quadratic, so a single instance can hold hundreds of
+/// thousands of files.
+pub struct SparseTsp {
+    signatures: Vec<Minhash>,
+    neighbors: Vec<Vec<usize>>,
+    // neighbor_distances[i][k] is the distance from i to neighbors[i][k]
+    neighbor_distances: Vec<Vec<u8>>,
+    entropy_weight: f64,
+}
+
+impl SparseTsp {
+    /// Builds an instance from signatures and candidate neighbor lists. The lists don't need to be
+    /// sorted, or to contain the true nearest neighbors, though local search will only consider
+    /// moves that join a node to one of its listed neighbors.
+    pub fn new(signatures: Vec<Minhash>, neighbors: Vec<Vec<usize>>) -> SparseTsp {
+        SparseTsp::with_entropy_weight(signatures, neighbors, 0.0)
+    }
+
+    /// Like new, but adds the difference in entropy between files to their distance, as
+    /// --entropy-weight does for the full distance matrix.
+    pub fn with_entropy_weight(
+        signatures: Vec<Minhash>,
+        neighbors: Vec<Vec<usize>>,
+        entropy_weight: f64,
+    ) -> SparseTsp {
+        assert!(signatures.len() == neighbors.len());
+        let mut sorted_neighbors = Vec::with_capacity(neighbors.len());
+        let mut neighbor_distances = Vec::with_capacity(neighbors.len());
+        for (i, candidates) in neighbors.into_iter().enumerate() {
+            let mut scored: Vec<(u8, usize)> = candidates
+                .into_iter()
+                .filter(|&j| j != i)
+                .map(|j| {
+                    (
+                        weighted_distance(&signatures[i], &signatures[j], entropy_weight),
+                        j,
+                    )
+                })
+                .collect();
+            scored.sort();
+            scored.dedup_by_key(|&mut (_, j)| j);
+            neighbor_distances.push(scored.iter().map(|&(d, _)| d).collect());
+            sorted_neighbors.push(scored.into_iter().map(|(_, j)| j).collect());
+        }
+        SparseTsp {
+            signatures,
+            neighbors: sorted_neighbors,
+            neighbor_distances,
+            entropy_weight,
+        }
+    }
+
+    fn cached_distance(&self, t1: usize, t2: usize) -> Option<u8> {
+        self.neighbors[t1]
+            .iter()
+            .position(|&n| n == t2)
+            .map(|k| self.neighbor_distances[t1][k])
+    }
+}
+
+impl TspInstance for SparseTsp {
+    fn count(&self) -> usize {
+        self.signatures.len()
+    }
+
+    fn retrieve_distance(&self, t1: usize, t2: usize) -> u64 {
+        if t1 == t2 {
+            return 0;
+        }
+        let distance = self
+            .cached_distance(t1, t2)
+            .or_else(|| self.cached_distance(t2, t1))
+            .unwrap_or_else(|| {
+                let (m1, m2) = (&self.signatures[t1], &self.signatures[t2]);
+                weighted_distance(m1, m2, self.entropy_weight)
+            });
+        distance as u64
+    }
+
+    fn neighbors(&self, index: usize) -> &[usize] {
+        &self.neighbors[index]
+    }
+}
+
+fn distance(m1: &Minhash, m2: &Minhash) -> u8 {
+    similarity_to_distance(m1.score(m2))
+}
+
+fn weighted_distance(m1: &Minhash, m2: &Minhash, entropy_weight: f64) -> u8 {
+    add_entropy_term(distance(m1, m2), m1, m2, entropy_weight)
+}
+
+/// Finds the k nearest neighbors of each signature by comparing every pair. This takes quadratic
+/// time, but only keeps k candidates per signature in memory.
+pub fn exact_neighbors(signatures: &[Minhash], k: usize) -> Vec<Vec<usize>> {
+    let count = signatures.len();
+    // Max heaps, so the farthest of the current candidates is the one we evict
+    let mut heaps: Vec<BinaryHeap<(u8, usize)>> = (0..count).map(|_| BinaryHeap::new()).collect();
+    for i in 0..count {
+        for j in i + 1..count {
+            let d = distance(&signatures[i], &signatures[j]);
+            offer(&mut heaps[i], k, d, j);
+            offer(&mut heaps[j], k, d, i);
+        }
+    }
+    heaps
+        .into_iter()
+        .map(|heap| heap.into_sorted_vec().into_iter().map(|(_, j)| j).collect())
+        .collect()
+}
+
+fn offer(heap: &mut BinaryHeap<(u8, usize)>, k: usize, distance: u8, index: usize) {
+    if heap.len() < k {
+        heap.push((distance, index));
+    } else if let Some(&(farthest, _)) = heap.peek() {
+        if distance < farthest {
+            heap.pop();
+            heap.push((distance, index));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::minhash::*;
+    use crate::sparse::*;
+    use crate::testutils::*;
+    use crate::tour::Tour;
+    use crate::twoopt::optimize_twoopt_path;
+
+    use std::fs::File;
+    use std::io::Write;
+    use tempfile::tempdir;
+
+    // Files in two families: runs of one letter, and runs of another, with growing lengths
+    fn build_signatures(count: usize) -> Vec<Minhash> {
+        let temp_dir = tempdir().unwrap();
+        (0..count)
+            .map(|i| {
+                let path = temp_dir.path().join(format!("{}", i));
+                let mut file = File::create(&path).unwrap();
+                let letter = if i % 2 == 0 { "abcdefgh" } else { "ijklmnop" };
+                for j in 0..(64 + i) {
+                    write!(file, "{}{}", letter, j % (4 + i / 2)).unwrap();
+                }
+                minhash_stream(&MinhashTarget::File(path)).unwrap()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn retrieve_distance_matches_signatures() {
+        let signatures = build_signatures(8);
+        let expected: Vec<Vec<u8>> = (0..8)
+            .map(|i| {
+                (0..8)
+                    .map(|j| distance(&signatures[i], &signatures[j]))
+                    .collect()
+            })
+            .collect();
+        let neighbors = exact_neighbors(&signatures, 2);
+        let tsp = SparseTsp::new(signatures, neighbors);
+        for (i, row) in expected.iter().enumerate() {
+            assert_eq!(2, tsp.neighbors(i).len());
+            for (j, &d) in row.iter().enumerate() {
+                let d = if i == j { 0 } else { d as u64 };
+                assert_eq!(d, tsp.retrieve_distance(i, j));
+            }
+        }
+    }
+
+    #[test]
+    fn exact_neighbors_are_nearest() {
+        let signatures = build_signatures(10);
+        let neighbors = exact_neighbors(&signatures, 3);
+        for i in 0..10 {
+            let farthest_neighbor = neighbors[i]
+                .iter()
+                .map(|&j| distance(&signatures[i], &signatures[j]))
+                .max()
+                .unwrap();
+            for j in 0..10 {
+                if i != j && !neighbors[i].contains(&j) {
+                    assert!(distance(&signatures[i], &signatures[j]) >= farthest_neighbor);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn twoopt_runs_on_sparse_tsp() {
+        let signatures = build_signatures(12);
+        let neighbors = exact_neighbors(&signatures, 4);
+        let tsp = SparseTsp::new(signatures, neighbors);
+        let identity: Vec<usize> = (0..12).collect();
+        let path = optimize_twoopt_path(&tsp, Tour::new(identity.clone()), None, None).unwrap();
+        check_permutation(&path, 11);
+        assert!(tsp.calculate_distance(&path) < tsp.calculate_distance(&identity));
+    }
+}
@@ -0,0 +1,273 @@
+use crate::tsp::TspInstance;
+
+// Lanczos steps between restarts. Memory is this many vectors of the instance size.
+const LANCZOS_STEPS: usize = 32;
+const MAX_RESTARTS: usize = 20;
+// Stop once the residual of the Ritz vector is this small, relative to the largest degree
+const TOLERANCE: f64 = 1e-6;
+
+/**
+ * Orders the instance by its Fiedler vector: the eigenvector of the second smallest eigenvalue of
+ * the graph Laplacian. Sorting by it places strongly connected nodes near each other, and unlike
+ * local search it looks at the whole graph at once.
+ *
+ * The graph connects each node to its neighbor list, in both directions, with weight 1 / (1 + d).
+ */
+pub fn spectral_order<I: TspInstance>(tsp: &I) -> Vec<usize> {
+    let count = tsp.count();
+    if count < 3 {
+        return (0..count).collect();
+    }
+    let laplacian = Laplacian::new(tsp);
+    let fiedler = fiedler_vector(&laplacian);
+    let mut order: Vec<usize> = (0..count).collect();
+    order.sort_by(|&a, &b| fiedler[a].total_cmp(&fiedler[b]).then(a.cmp(&b)));
+    order
+}
+
+struct Laplacian {
+    adjacency: Vec<Vec<(usize, f64)>>,
+    degrees: Vec<f64>,
+}
+
+impl Laplacian {
+    fn new<I: TspInstance>(tsp: &I) -> Laplacian {
+        let count = tsp.count();
+        let mut edges: Vec<(usize, usize)> = (0..count)
+            .flat_map(|i| {
+                tsp.neighbors(i)
+                    .iter()
+                    .filter(move |&&j| j != i)
+                    .map(move |&j| (i.min(j), i.max(j)))
+            })
+            .collect();
+        edges.sort_unstable();
+        edges.dedup();
+        let mut adjacency = vec![Vec::new(); count];
+        let mut degrees = vec![0.0; count];
+        for (i, j) in edges {
+            let weight = 1.0 / (1.0 + tsp.retrieve_distance(i, j) as f64);
+            adjacency[i].push((j, weight));
+            adjacency[j].push((i, weight));
+            degrees[i] += weight;
+            degrees[j] += weight;
+        }
+        Laplacian { adjacency, degrees }
+    }
+
+    fn apply(&self, x: &[f64]) -> Vec<f64> {
+        self.adjacency
+            .iter()
+            .enumerate()
+            .map(|(i, edges)| {
+                self.degrees[i] * x[i] - edges.iter().map(|&(j, w)| w * x[j]).sum::<f64>()
+            })
+            .collect()
+    }
+}
+
+/**
+ * Restarted Lanczos with full reorthogonalization. The constant vector is always an eigenvector
+ * with eigenvalue 0, so we work in its orthogonal complement, where the smallest eigenvalue is
+ * the one we want. Each cycle builds a small tridiagonal matrix, takes the Ritz vector of its
+ * smallest eigenvalue, and restarts from it.
+ */
+fn fiedler_vector(laplacian: &Laplacian) -> Vec<f64> {
+    let count = laplacian.degrees.len();
+    let scale = laplacian.degrees.iter().cloned().fold(1.0, f64::max);
+    // Starting from the current order means a graph with no structure leaves the order alone
+    let mut x: Vec<f64> = (0..count).map(|i| i as f64).collect();
+    for restart in 0..MAX_RESTARTS {
+        remove_mean(&mut x);
+        normalize(&mut x);
+        let (ritz, residual) = lanczos_cycle(laplacian, &x);
+        x = ritz;
+        log::debug!("Spectral restart {}, residual={:e}", restart, residual);
+        if residual < TOLERANCE * scale {
+            break;
+        }
+    }
+    x
+}
+
+// Returns the Ritz vector for the smallest eigenvalue, and the norm of its residual
+fn lanczos_cycle(laplacian: &Laplacian, start: &[f64]) -> (Vec<f64>, f64) {
+    let mut basis: Vec<Vec<f64>> = vec![start.to_vec()];
+    let mut alphas = Vec::new();
+    let mut betas: Vec<f64> = Vec::new();
+    for step in 0..LANCZOS_STEPS.min(start.len() - 1) {
+        let mut w = laplacian.apply(&basis[step]);
+        alphas.push(dot(&w, &basis[step]));
+        // Full reorthogonalization, twice is enough
+        for _ in 0..2 {
+            remove_mean(&mut w);
+            for q in &basis {
+                let projection = dot(&w, q);
+                axpy(&mut w, -projection, q);
+            }
+        }
+        let beta = dot(&w, &w).sqrt();
+        if beta < 1e-12 || step + 1 == LANCZOS_STEPS.min(start.len() - 1) {
+            break;
+        }
+        betas.push(beta);
+        w.iter_mut().for_each(|v| *v /= beta);
+        basis.push(w);
+    }
+
+    let k = alphas.len();
+    let mut tridiagonal = vec![0.0; k * k];
+    for i in 0..k {
+        tridiagonal[i * k + i] = alphas[i];
+        if i + 1 < k {
+            tridiagonal[i * k + i + 1] = betas[i];
+            tridiagonal[(i + 1) * k + i] = betas[i];
+        }
+    }
+    let (eigenvalues, eigenvectors) = jacobi_eigen(tridiagonal, k);
+    let smallest = (0..k)
+        .min_by(|&a, &b| eigenvalues[a].total_cmp(&eigenvalues[b]))
+        .unwrap();
+
+    let mut ritz = vec![0.0; start.len()];
+    for (i, q) in basis.iter().take(k).enumerate() {
+        axpy(&mut ritz, eigenvectors[i * k + smallest], q);
+    }
+    normalize(&mut ritz);
+    let mut residual = laplacian.apply(&ritz);
+    axpy(&mut residual, -eigenvalues[smallest], &ritz);
+    (ritz, dot(&residual, &residual).sqrt())
+}
+
+// Eigen decomposition of a small symmetric matrix by cyclic Jacobi rotations. Returns the
+// eigenvalues, and the eigenvectors as the columns of a row-major matrix.
+fn jacobi_eigen(mut a: Vec<f64>, n: usize) -> (Vec<f64>, Vec<f64>) {
+    let mut v = vec![0.0; n * n];
+    for i in 0..n {
+        v[i * n + i] = 1.0;
+    }
+    for _ in 0..100 {
+        let off_diagonal: f64 = (0..n)
+            .flat_map(|i| (0..n).filter(move |&j| j != i).map(move |j| (i, j)))
+            .map(|(i, j)| a[i * n + j] * a[i * n + j])
+            .sum();
+        if off_diagonal < 1e-22 {
+            break;
+        }
+        for p in 0..n {
+            for q in p + 1..n {
+                let apq = a[p * n + q];
+                if apq.abs() < 1e-300 {
+                    continue;
+                }
+                let theta = (a[q * n + q] - a[p * n + p]) / (2.0 * apq);
+                let t = theta.signum() / (theta.abs() + (theta * theta + 1.0).sqrt());
+                let t = if theta == 0.0 { 1.0 } else { t };
+                let c = 1.0 / (t * t + 1.0).sqrt();
+                let s = t * c;
+                for k in 0..n {
+                    let akp = a[k * n + p];
+                    let akq = a[k * n + q];
+                    a[k * n + p] = c * akp - s * akq;
+                    a[k * n + q] = s * akp + c * akq;
+                }
+                for k in 0..n {
+                    let apk = a[p * n + k];
+                    let aqk = a[q * n + k];
+                    a[p * n + k] = c * apk - s * aqk;
+                    a[q * n + k] = s * apk + c * aqk;
+                }
+                for k in 0..n {
+                    let vkp = v[k * n + p];
+                    let vkq = v[k * n + q];
+                    v[k * n + p] = c * vkp - s * vkq;
+                    v[k * n + q] = s * vkp + c * vkq;
+                }
+            }
+        }
+    }
+    ((0..n).map(|i| a[i * n + i]).collect(), v)
+}
+
+fn dot(x: &[f64], y: &[f64]) -> f64 {
+    x.iter().zip(y).map(|(a, b)| a * b).sum()
+}
+
+// x += factor * y
+fn axpy(x: &mut [f64], factor: f64, y: &[f64]) {
+    x.iter_mut().zip(y).for_each(|(a, b)| *a += factor * b);
+}
+
+fn remove_mean(x: &mut [f64]) {
+    let mean = x.iter().sum::<f64>() / x.len() as f64;
+    x.iter_mut().for_each(|v| *v -= mean);
+}
+
+fn normalize(x: &mut [f64]) {
+    let norm = dot(x, x).sqrt();
+    if norm > 0.0 {
+        x.iter_mut().for_each(|v| *v /= norm);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::spectral::*;
+    use crate::testutils::*;
+    use crate::tsp::Tsp;
+
+    use rand::rngs::StdRng;
+    use rand::seq::SliceRandom;
+    use rand::SeedableRng;
+
+    #[test]
+    fn jacobi_finds_eigenvalues() {
+        let (values, vectors) = jacobi_eigen(vec![2.0, 1.0, 1.0, 2.0], 2);
+        let mut sorted = values.clone();
+        sorted.sort_by(f64::total_cmp);
+        assert!((sorted[0] - 1.0).abs() < 1e-9 && (sorted[1] - 3.0).abs() < 1e-9);
+        // Each column is an eigenvector
+        for i in 0..2 {
+            let (x, y) = (vectors[i], vectors[2 + i]);
+            assert!((2.0 * x + y - values[i] * x).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn spectral_order_recovers_line() {
+        let count = 40;
+        let mut rng = StdRng::seed_from_u64(34);
+        let mut shuffled: Vec<usize> = (0..count).collect();
+        shuffled.shuffle(&mut rng);
+        let linear = build_linear_distances(count);
+        let mut full = vec![0u8; count * count];
+        for i in 0..count {
+            for j in 0..count {
+                full[i * count + j] = linear[shuffled[i] * count + shuffled[j]];
+            }
+        }
+        let tsp = Tsp::new(full, count);
+        let order = spectral_order(&tsp);
+        check_permutation(&order, count - 1);
+        assert_eq!(count as u64 - 1, tsp.calculate_distance(&order));
+    }
+
+    #[test]
+    fn spectral_order_separates_clusters() {
+        // Two groups, distance 1 inside a group and 100 across groups
+        let count = 20;
+        let mut full = vec![0u8; count * count];
+        for i in 0..count {
+            for j in 0..count {
+                if i != j {
+                    full[i * count + j] = if i % 2 == j % 2 { 1 } else { 100 };
+                }
+            }
+        }
+        let tsp = Tsp::new(full, count);
+        let order = spectral_order(&tsp);
+        check_permutation(&order, count - 1);
+        let first_half: Vec<usize> = order[..10].iter().map(|i| i % 2).collect();
+        assert!(first_half.iter().all(|&group| group == first_half[0]));
+    }
+}
@@ -0,0 +1,167 @@
+use crate::minhash::{similarity_to_distance, Minhash};
+
+// Seam cost when either side has no signature to compare
+const UNKNOWN_DISTANCE: u64 = 255;
+
+/// The files at the two ends of an already ordered batch
+pub struct BatchEnds<'a> {
+    pub first: Option<&'a Minhash>,
+    pub last: Option<&'a Minhash>,
+}
+
+/**
+ * Chooses an order for already optimized batches, and for each batch whether to emit it reversed,
+ * so that the files on either side of each seam between batches are as similar as possible.
+ *
+ * This is a path tsp over the batches where each batch can be entered from either end. We take
+ * the best greedy chain over every starting batch and orientation, then improve it with 2-opt.
+ * Reversing a run of batches also flips each of them, so the seams inside the run keep their cost
+ * and only the two seams at its boundary change. Unless `reversible`, as for batches ordered by
+ * an asymmetric tsp, where reading a batch backwards would change its cost, every batch keeps
+ * its orientation and the greedy chain is kept as it is.
+ *
+ * Returns (batch index, reversed) pairs in output order.
+ */
+pub fn stitch_batches(ends: &[BatchEnds], reversible: bool) -> Vec<(usize, bool)> {
+    let count = ends.len();
+    if count <= 1 {
+        return (0..count).map(|b| (b, false)).collect();
+    }
+    // Endpoint 2b is the first file of batch b, 2b + 1 is its last
+    let endpoints: Vec<Option<&Minhash>> = ends.iter().flat_map(|e| [e.first, e.last]).collect();
+    let distances: Vec<Vec<u64>> = endpoints
+        .iter()
+        .map(|e1| endpoints.iter().map(|e2| distance(*e1, *e2)).collect())
+        .collect();
+    let seam = |(b1, r1): (usize, bool), (b2, r2): (usize, bool)| {
+        let exit = if r1 { 2 * b1 } else { 2 * b1 + 1 };
+        let entry = if r2 { 2 * b2 + 1 } else { 2 * b2 };
+        distances[exit][entry]
+    };
+    let total = |sequence: &[(usize, bool)]| -> u64 {
+        sequence.windows(2).map(|pair| seam(pair[0], pair[1])).sum()
+    };
+
+    let orientations: &[bool] = if reversible { &[false, true] } else { &[false] };
+    let oriented = |b: usize| orientations.iter().map(move |&r| (b, r));
+
+    let mut best: Vec<(usize, bool)> = (0..count).map(|b| (b, false)).collect();
+    let mut best_total = total(&best);
+    for start in (0..count).flat_map(oriented) {
+        let mut used = vec![false; count];
+        let mut sequence = vec![start];
+        used[start.0] = true;
+        while sequence.len() < count {
+            let last = *sequence.last().unwrap();
+            let next = (0..count)
+                .filter(|&b| !used[b])
+                .flat_map(oriented)
+                .min_by_key(|&candidate| seam(last, candidate))
+                .unwrap();
+            used[next.0] = true;
+            sequence.push(next);
+        }
+        let sequence_total = total(&sequence);
+        if sequence_total < best_total {
+            best = sequence;
+            best_total = sequence_total;
+        }
+    }
+
+    let flip = |(b, r): (usize, bool)| (b, !r);
+    let mut improved = reversible;
+    while improved {
+        improved = false;
+        for i in 0..count {
+            for j in i + 1..count {
+                let mut old_distance = 0;
+                let mut new_distance = 0;
+                if i > 0 {
+                    old_distance += seam(best[i - 1], best[i]);
+                    new_distance += seam(best[i - 1], flip(best[j]));
+                }
+                if j + 1 < count {
+                    old_distance += seam(best[j], best[j + 1]);
+                    new_distance += seam(flip(best[i]), best[j + 1]);
+                }
+                if new_distance < old_distance {
+                    best[i..=j].reverse();
+                    for batch in best[i..=j].iter_mut() {
+                        *batch = flip(*batch);
+                    }
+                    improved = true;
+                }
+            }
+        }
+    }
+    log::debug!("Stitched {} batches, seam distance={}", count, total(&best));
+    best
+}
+
+fn distance(m1: Option<&Minhash>, m2: Option<&Minhash>) -> u64 {
+    match (m1, m2) {
+        (Some(m1), Some(m2)) => similarity_to_distance(m1.score(m2)) as u64,
+        _ => UNKNOWN_DISTANCE,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::stitch::*;
+    use crate::testutils::*;
+
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    fn ends<'a>(first: &'a Minhash, last: &'a Minhash) -> BatchEnds<'a> {
+        BatchEnds {
+            first: Some(first),
+            last: Some(last),
+        }
+    }
+
+    #[test]
+    fn single_batch_is_unchanged() {
+        let signature = minhash_bytes(b"a single batch with one file in it");
+        assert_eq!(
+            vec![(0, false)],
+            stitch_batches(&[ends(&signature, &signature)], true)
+        );
+    }
+
+    #[test]
+    fn batches_are_joined_at_similar_ends() {
+        let mut rng = StdRng::seed_from_u64(32);
+        let contents: Vec<Vec<u8>> = (0..4)
+            .map(|_| (0..2048).map(|_| rng.gen()).collect())
+            .collect();
+        let w = minhash_bytes(&contents[0]);
+        let x = minhash_bytes(&contents[1]);
+        let y = minhash_bytes(&contents[2]);
+        let z = minhash_bytes(&contents[3]);
+        // The best chain is batch 2, then batch 0 reversed, then batch 1: w..x x..y y..z
+        let batches = [ends(&y, &x), ends(&y, &z), ends(&w, &x)];
+        let order = stitch_batches(&batches, true);
+        let expected = vec![(2, false), (0, true), (1, false)];
+        let reversed: Vec<(usize, bool)> = expected.iter().rev().map(|&(b, r)| (b, !r)).collect();
+        assert!(order == expected || order == reversed, "{:?}", order);
+    }
+
+    #[test]
+    fn irreversible_batches_keep_their_orientation() {
+        let mut rng = StdRng::seed_from_u64(33);
+        let signatures: Vec<Minhash> = (0..3)
+            .map(|_| minhash_bytes(&(0..2048).map(|_| rng.gen()).collect::<Vec<u8>>()))
+            .collect();
+        // Both batches end at the same file, so the best join reverses one of them
+        let batches = [
+            ends(&signatures[0], &signatures[1]),
+            ends(&signatures[2], &signatures[1]),
+        ];
+        let order = stitch_batches(&batches, true);
+        assert!(order.iter().any(|&(_, reversed)| reversed), "{:?}", order);
+        let order = stitch_batches(&batches, false);
+        assert!(order.iter().all(|&(_, reversed)| !reversed), "{:?}", order);
+        check_permutation(&order.iter().map(|&(b, _)| b).collect(), 1);
+    }
+}
@@ -5,6 +5,7 @@ use rand::Rng;
 use std::fs::read_to_string;
 use std::path::PathBuf;
 
+use crate::minhash::{minhash_target, Minhash, MinhashSettings, MinhashTarget};
 use crate::tsp::Tsp;
 
 #[cfg(test)]
@@ -162,4 +163,15 @@ pub fn read_tsp_file(path: PathBuf) -> Result<Option<Vec<(f32, f32)>>, std::io::
         }
     }
     Ok(Option::Some(coordinates))
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+pub fn minhash_bytes(bytes: &[u8]) -> Minhash {
+    use std::io::Write;
+
+    let mut file = tempfile::NamedTempFile::new().unwrap();
+    file.write_all(bytes).unwrap();
+    let path = file.into_temp_path();
+    let target = MinhashTarget::File(path.to_path_buf());
+    minhash_target(&target, MinhashSettings::default(), false).unwrap()
+}
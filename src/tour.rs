@@ -1,5 +1,3 @@
-use std::cmp::{max, min};
-
 #[derive(Debug)]
 pub struct Tour {
     tour: Vec<usize>,
@@ -18,16 +16,20 @@ impl Tour {
             inverse: inverse,
         }
     }
+}
 
-    pub fn len(&self) -> usize {
+impl CyclicTour for Tour {
+    fn len(&self) -> usize {
         return self.tour.len();
     }
 
-    pub fn flip(&mut self, a: usize, b: usize) {
-        assert!(a != b);
-        let mut first = min(self.inverse[a], self.inverse[b]);
-        let mut second = max(self.inverse[a], self.inverse[b]);
-        while first < second {
+    // Reverses the path in place, wrapping around the end of the array if it has to
+    fn flip(&mut self, a: usize, b: usize) {
+        let count = self.len();
+        let mut first = self.inverse[a];
+        let mut second = self.inverse[b];
+        let length = (second + count - first) % count + 1;
+        for _ in 0..length / 2 {
             let left = self.tour[first];
             let right = self.tour[second];
             self.tour[first] = right;
@@ -35,12 +37,12 @@ impl Tour {
             self.inverse[right] = first;
             self.inverse[left] = second;
 
-            first += 1;
-            second -= 1;
+            first = (first + 1) % count;
+            second = (second + count - 1) % count;
         }
     }
 
-    pub fn next(&self, a: usize) -> usize {
+    fn next(&self, a: usize) -> usize {
         let idx = self.inverse[a];
         if idx == self.len() - 1 {
             self.tour[0]
@@ -49,7 +51,7 @@ impl Tour {
         }
     }
 
-    pub fn prev(&self, a: usize) -> usize {
+    fn prev(&self, a: usize) -> usize {
         let idx = self.inverse[a];
         if idx == 0 {
             self.tour[self.len() - 1]
@@ -58,21 +60,92 @@ impl Tour {
         }
     }
 
-    pub fn simple_between(&self, a: usize, b: usize, candidate: usize) -> bool {
-        let left = min(self.inverse[a], self.inverse[b]);
-        let right = max(self.inverse[a], self.inverse[b]);
-        return self.inverse[candidate] > left && self.inverse[candidate] < right;
+    fn between(&self, a: usize, b: usize, c: usize) -> bool {
+        between_positions(self.inverse[a], self.inverse[b], self.inverse[c])
+    }
+
+    fn to_indices(self) -> Vec<usize> {
+        return self.tour;
+    }
+
+    fn to_path(&self, first: usize) -> Vec<usize> {
+        let start = self.inverse[first];
+        let mut path = Vec::with_capacity(self.len());
+        path.extend_from_slice(&self.tour[start..]);
+        path.extend_from_slice(&self.tour[..start]);
+        path
+    }
+}
+
+/// The operations local search needs from a tour, so it can run over a plain array, where flips
+/// are O(n), or a two-level list, where they are O(√n).
+pub trait CyclicTour {
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
     }
 
-    pub fn are_neighbors(&self, a: usize, b: usize) -> bool {
+    fn next(&self, a: usize) -> usize;
+
+    fn prev(&self, a: usize) -> usize;
+
+    /// Whether b is on the path from a forward to c, counting both ends. This follows the tour's
+    /// direction and wraps around, so between(a, b, c) and between(c, b, a) only agree when b is
+    /// a or c.
+    fn between(&self, a: usize, b: usize, c: usize) -> bool;
+
+    /// Whether the nodes are met in this order walking forward from the first of them.
+    fn sequence(&self, nodes: &[usize]) -> bool {
+        nodes
+            .windows(2)
+            .all(|pair| self.between(nodes[0], pair[0], pair[1]))
+    }
+
+    /// Reverses the path from a forward to b, so that a follows b's old successor and b follows
+    /// a's old predecessor. Implementations may reverse the rest of the tour instead, which gives
+    /// the same cycle, traversed in the other direction.
+    fn flip(&mut self, a: usize, b: usize);
+
+    fn are_neighbors(&self, a: usize, b: usize) -> bool {
         self.next(a) == b || self.prev(a) == b
     }
 
-    pub fn to_indices(self) -> Vec<usize> {
-        return self.tour;
+    /// Every node, in tour order from wherever the representation happens to start.
+    fn to_indices(self) -> Vec<usize>
+    where
+        Self: Sized;
+
+    /// Cuts the cycle open in front of `first`, returning the nodes in tour order starting from it.
+    fn to_path(&self, first: usize) -> Vec<usize> {
+        let mut path = Vec::with_capacity(self.len());
+        let mut node = first;
+        for _ in 0..self.len() {
+            path.push(node);
+            node = self.next(node);
+        }
+        path
     }
 }
 
+// between() for positions in a cyclic sequence
+pub(crate) fn between_positions<T: PartialOrd>(a: T, b: T, c: T) -> bool {
+    if a <= c {
+        a <= b && b <= c
+    } else {
+        b >= a || b <= c
+    }
+}
+
+// Position of every node in a path, the inverse of the path itself
+pub fn positions(path: &[usize]) -> Vec<usize> {
+    let mut positions = vec![0; path.len()];
+    for (position, &node) in path.iter().enumerate() {
+        positions[node] = position;
+    }
+    positions
+}
+
 #[cfg(test)]
 mod tests {
     use proptest::prelude::*;
@@ -90,21 +163,9 @@ mod tests {
             let original_indices = indices.clone();
             let mut tour = Tour::new(indices);
             if a != b {
-            //     let min = min(a, b);
-            //     let max = max(a,b);
-
                 tour.flip(a, b);
-            //     assert_ne!(original_indices, tour.tour);
-            //     if max - 1 > min {
-            //         let between = max - 1;
-            //         assert!(tour.between(a, b, between));
-            //     }
-                 tour.flip(a, b);
-            //     assert_eq!(original_indices, tour.tour);
-            //     if max - 1 > min {
-            //         let between = max - 1;
-            //         assert!(tour.between(a, b, between));
-            //     }
+                // The path from a to b is now the path from b to a
+                tour.flip(b, a);
             }
             let flipped_indices = tour.to_indices();
             assert_eq!(original_indices, flipped_indices);
@@ -135,6 +196,35 @@ mod tests {
         assert_eq!(tour.to_indices(), vec![4, 5, 1, 3, 0, 2, 6]);
     }
 
+    #[test]
+    fn to_path_rotates_to_first() {
+        let tour = Tour::new(vec![4, 6, 2, 0, 3, 1, 5]);
+        assert_eq!(tour.to_path(0), vec![0, 3, 1, 5, 4, 6, 2]);
+        assert_eq!(tour.to_path(4), vec![4, 6, 2, 0, 3, 1, 5]);
+    }
+
+    #[test]
+    fn between_wraps_around() {
+        let tour = Tour::new(vec![4, 6, 2, 0, 3, 1, 5]);
+        assert!(tour.between(3, 5, 6));
+        assert!(tour.between(3, 4, 6));
+        assert!(!tour.between(3, 2, 6));
+        assert!(tour.between(6, 2, 3));
+        assert!(!tour.between(6, 5, 3));
+        assert!(tour.between(1, 1, 1));
+        assert!(!tour.between(1, 5, 1));
+    }
+
+    #[test]
+    fn sequence_follows_orientation() {
+        let mut tour = Tour::new(vec![4, 6, 2, 0, 3, 1, 5]);
+        assert!(tour.sequence(&[2, 3, 5, 6]));
+        assert!(tour.sequence(&[5, 4, 2]));
+        assert!(!tour.sequence(&[2, 5, 3]));
+        tour.flip(0, 1);
+        assert!(tour.sequence(&[2, 1, 3, 0, 5]));
+    }
+
     #[test]
     fn tour_is_tour_2() {
         let length = 6;
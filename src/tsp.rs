@@ -7,66 +7,55 @@ use num_traits::bounds::Bounded;
 use std::collections::BinaryHeap;
 use std::collections::HashSet;
 
-use crate::tour::Tour;
+use crate::distances::TriangularDistances;
+use crate::tour::CyclicTour;
 
 // The LK paper says they only use the 5 nearest nodes
-const NEIGHBOR_SIZE: usize = 15;
+pub(crate) const NEIGHBOR_SIZE: usize = 15;
 
-#[derive(Clone)]
-pub struct Tsp<T: PrimInt> {
-    pub distances: Vec<T>,
-    pub count: usize,
-    pub neighbors: Vec<Vec<usize>>,
-}
+/// The operations local search needs from a tsp instance, so it can run over a dense distance
+/// matrix or over a sparse instance that only keeps neighbor lists.
+pub trait TspInstance {
+    fn count(&self) -> usize;
 
-impl<T: PrimInt> Tsp<T> {
-    pub fn new(distances: Vec<T>, count: usize) -> Tsp<T> {
-        let neighbors = build_neighbors(count, &distances);
-        return Tsp {
-            distances,
-            count,
-            neighbors,
-        };
-    }
+    fn retrieve_distance(&self, t1: usize, t2: usize) -> u64;
 
-    pub fn retrieve_distance(&self, t1: usize, t2: usize) -> u64 {
-        return self.distances[distance_index(t1, t2, self.count)]
-            .to_u64()
-            .unwrap();
-    }
+    // The closest nodes to index, closest first
+    fn neighbors(&self, index: usize) -> &[usize];
 
-    pub fn calculate_distance(&self, indices: &[usize]) -> u64 {
+    // Length of the open path through indices, with no edge from the last index back to the first
+    fn calculate_distance(&self, indices: &[usize]) -> u64 {
         let mut distance: u64 = 0;
-        for i in 0..indices.len() - 1 {
-            distance += self.retrieve_distance(indices[i], indices[(i + 1) % self.count]);
+        for pair in indices.windows(2) {
+            distance += self.retrieve_distance(pair[0], pair[1]);
         }
-        return distance;
+        distance
     }
 
-    pub fn calculate_distance_tour(&self, tour: &Tour) -> u64 {
+    fn calculate_distance_tour<T: CyclicTour>(&self, tour: &T) -> u64 {
         let mut distance: u64 = 0;
         let mut index = 0;
         for _ in 0..tour.len() {
             distance += self.retrieve_distance(index, tour.next(index));
             index = tour.next(index);
         }
-        return distance;
+        distance
     }
 
-    pub fn generate_nearest_neighbor_tour(&self, index: usize) -> Vec<usize> {
+    fn generate_nearest_neighbor_tour(&self, index: usize) -> Vec<usize> {
         let mut used = HashSet::new();
         let mut tour = vec![index];
         used.insert(index);
         let mut remaining = HashSet::new();
-        (0..self.count).for_each(|elem| {
+        (0..self.count()).for_each(|elem| {
             if elem != index {
                 remaining.insert(elem);
             }
         });
         let mut last_index = index;
-        while tour.len() < self.count {
+        while tour.len() < self.count() {
             let mut next_index = Option::None;
-            for node_distance in self.neighbors[last_index].iter() {
+            for node_distance in self.neighbors(last_index).iter() {
                 if used.contains(&node_distance) {
                     continue;
                 } else {
@@ -97,6 +86,44 @@ impl<T: PrimInt> Tsp<T> {
     }
 }
 
+#[derive(Clone)]
+pub struct Tsp<T: PrimInt> {
+    pub distances: TriangularDistances<T>,
+    pub count: usize,
+    pub neighbors: Vec<Vec<usize>>,
+}
+
+impl<T: PrimInt> Tsp<T> {
+    // Takes a full, symmetric count * count matrix
+    pub fn new(distances: Vec<T>, count: usize) -> Tsp<T> {
+        Tsp::from_distances(TriangularDistances::from_full(&distances, count))
+    }
+
+    pub fn from_distances(distances: TriangularDistances<T>) -> Tsp<T> {
+        let count = distances.count();
+        let neighbors = build_neighbors(&distances);
+        return Tsp {
+            distances,
+            count,
+            neighbors,
+        };
+    }
+}
+
+impl<T: PrimInt> TspInstance for Tsp<T> {
+    fn count(&self) -> usize {
+        self.count
+    }
+
+    fn retrieve_distance(&self, t1: usize, t2: usize) -> u64 {
+        self.distances.get(t1, t2).to_u64().unwrap()
+    }
+
+    fn neighbors(&self, index: usize) -> &[usize] {
+        &self.neighbors[index]
+    }
+}
+
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub struct Edge {
     pub left: usize,
@@ -118,10 +145,10 @@ impl Edge {
 
 // Prim's algorithm
 fn minimal_spanning_tree<T: PrimInt + Bounded>(
-    count: usize,
-    distances: &[T],
+    distances: &TriangularDistances<T>,
     neighbors: &Vec<Vec<usize>>,
 ) -> Vec<Edge> {
+    let count = distances.count();
     let mut edges = Vec::new();
     let mut seen = BitSet::with_capacity(count);
 
@@ -131,13 +158,13 @@ fn minimal_spanning_tree<T: PrimInt + Bounded>(
     let mut threshold: T = T::zero();
     for i in 0..neighbors.len() {
         let first_neighbor = neighbors[i][0];
-        let distance = distances[distance_index(i, first_neighbor, count)];
+        let distance = distances.get(i, first_neighbor);
         threshold = threshold.max(distance);
     }
 
     let mut remaining = BinaryHeap::new();
     for i in 1..count {
-        let distance = distances[distance_index(0, i, count)];
+        let distance = distances.get(0, i);
         remaining.push(EdgeDistance {
             distance: T::max_value() - distance,
             left: 0,
@@ -164,7 +191,7 @@ fn minimal_spanning_tree<T: PrimInt + Bounded>(
                 seen.insert(new_index);
                 for i in 0..count {
                     if new_index != i && !seen.contains(i) {
-                        let distance = distances[distance_index(new_index, i, count)];
+                        let distance = distances.get(new_index, i);
                         if distance <= threshold {
                             remaining.push(EdgeDistance {
                                 distance: T::max_value() - distance,
@@ -180,7 +207,7 @@ fn minimal_spanning_tree<T: PrimInt + Bounded>(
                     if !seen.contains(i) {
                         for j in 0..count {
                             if seen.contains(j) {
-                                let distance = distances[distance_index(i, j, count)];
+                                let distance = distances.get(i, j);
                                 remaining.push(EdgeDistance {
                                     distance: T::max_value() - distance,
                                     left: i,
@@ -198,34 +225,25 @@ fn minimal_spanning_tree<T: PrimInt + Bounded>(
 }
 
 fn alpha_neighbors<T: PrimInt + Bounded>(
-    count: usize,
-    distances: &[T],
+    distances: &TriangularDistances<T>,
     neighbors: &Vec<Vec<usize>>,
 ) -> Vec<Vec<usize>> {
     let mut alpha_neighbors = Vec::new();
     // need to create minimum 1-tree
-    let spanning_tree = minimal_spanning_tree(count, distances, neighbors);
+    let spanning_tree = minimal_spanning_tree(distances, neighbors);
     return alpha_neighbors;
 }
 
-fn build_neighbors<T: PrimInt>(count: usize, distances: &[T]) -> Vec<Vec<usize>> {
+fn build_neighbors<T: PrimInt>(distances: &TriangularDistances<T>) -> Vec<Vec<usize>> {
+    let count = distances.count();
     let mut neighbors = Vec::new();
-    assert!(distances.len() == count * count);
     for i in 0..count {
         let mut heap: BinaryHeap<NodeDistance<T>> = BinaryHeap::new();
         for j in 0..count {
             if i == j {
                 continue;
             }
-            let distance_index = distance_index(i, j, count);
-            if distance_index > distances.len() {
-                panic!(
-                    "Index {} is out of bounds for length {}",
-                    distance_index,
-                    distances.len()
-                );
-            }
-            let computed_distance = distances[distance_index];
+            let computed_distance = distances.get(i, j);
             if heap.len() < NEIGHBOR_SIZE {
                 heap.push(NodeDistance {
                     distance: computed_distance,
@@ -261,14 +279,6 @@ fn build_neighbors<T: PrimInt>(count: usize, distances: &[T]) -> Vec<Vec<usize>>
     neighbors
 }
 
-fn distance_index(t1: usize, t2: usize, count: usize) -> usize {
-    if t1 < t2 {
-        return t1 * count + t2;
-    } else {
-        return t2 * count + t1;
-    }
-}
-
 #[derive(PartialOrd, Ord, PartialEq, Eq, Debug)]
 struct NodeDistance<T: PrimInt> {
     distance: T,
@@ -287,8 +297,9 @@ mod tests {
 
     use std::collections::HashSet;
 
-    use crate::tsp::{build_neighbors, distance_index, minimal_spanning_tree, Tsp, Edge};
+    use crate::distances::TriangularDistances;
     use crate::testutils::*;
+    use crate::tsp::{build_neighbors, minimal_spanning_tree, Edge, Tsp, TspInstance};
 
     use proptest::prelude::*;
 
@@ -335,8 +346,8 @@ mod tests {
             0, 2, 4, 6, 6, 8, 10, 2, 0, 2, 4, 4, 6, 8, 4, 2, 0, 2, 2, 4, 6, 6, 4, 2, 0, 4, 6, 8, 6,
             4, 2, 4, 0, 2, 4, 8, 6, 4, 6, 2, 0, 2, 10, 8, 6, 8, 4, 2, 0,
         ];
-        let spanning_tree =
-            minimal_spanning_tree(count, &distances, &build_neighbors(count, &distances));
+        let distances = TriangularDistances::from_full(&distances, count);
+        let spanning_tree = minimal_spanning_tree(&distances, &build_neighbors(&distances));
         let mut nodes = HashSet::new();
         for edge in &spanning_tree {
             nodes.insert(edge.left);
@@ -347,7 +358,7 @@ mod tests {
         }
         let mut distance = 0;
         for edge in &spanning_tree {
-            distance += distances[distance_index(edge.left, edge.right, count)];
+            distance += distances.get(edge.left, edge.right);
         }
         assert_eq!(12, distance);
     }
@@ -361,7 +372,8 @@ mod tests {
         })
     ) {
         let distances: Vec<u16> = build_geometric_distances(&v);
-        let tree = minimal_spanning_tree(v.len(), &distances, &build_neighbors(v.len(), &distances));
+        let distances = TriangularDistances::from_full(&distances, v.len());
+        let tree = minimal_spanning_tree(&distances, &build_neighbors(&distances));
         check_spanning_tree_is_a_spanning_tree(v.len(), &tree);
     }
     }
@@ -385,19 +397,19 @@ mod tests {
     #[test]
     fn minimal_spanning_tree_generates_minimal_tree_for_geo_sample() {
         let count = 7;
-        let distances = build_geo_sample();
-        let spanning_tree = minimal_spanning_tree(count, &distances, &build_neighbors(count, &distances));
+        let distances = TriangularDistances::from_full(&build_geo_sample(), count);
+        let spanning_tree = minimal_spanning_tree(&distances, &build_neighbors(&distances));
         let mut distance: u16 = 0;
         for edge in &spanning_tree {
-            distance += distances[distance_index(edge.left, edge.right, count)] as u16;
+            distance += distances.get(edge.left, edge.right) as u16;
         }
         assert_eq!(51, distance);
     }
 
     #[test]
     fn build_neighbors_works_for_linear_distances() {
-        let distances = build_linear_distances(20);
-        let neighbors = build_neighbors(20, &distances);
+        let distances = TriangularDistances::from_full(&build_linear_distances(20), 20);
+        let neighbors = build_neighbors(&distances);
         let neighbors_0 = &neighbors[0];
         for i in 1..16 {
             assert!(neighbors_0.contains(&i));
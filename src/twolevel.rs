@@ -0,0 +1,296 @@
+use crate::tour::{between_positions, CyclicTour};
+
+// From this many nodes on, 2-opt runs faster on a two-level list than on an array. The two break
+// even at about a thousand nodes; see benches/tours.rs.
+pub const TWO_LEVEL_THRESHOLD: usize = 1000;
+
+/**
+ * A tour stored as a two-level list (Fredman et al., 1995). The tour is cut into segments of
+ * about √n nodes. Each segment keeps its nodes in an array, and a reversed bit saying whether to
+ * read that array backwards. The segments themselves are kept in tour order, each knowing its
+ * rank in that order.
+ *
+ * A flip splits at most two segments so the path starts and ends on segment boundaries, then
+ * reverses the order of the segments on the path (or on the rest of the tour, whichever has
+ * fewer) and toggles their reversed bits. That's O(√n) work rather than the O(n) an array needs.
+ * Splits leave more and smaller segments behind, so once there are twice as many as we started
+ * with, we rebuild the list from scratch.
+ */
+pub struct TwoLevelTour {
+    segments: Vec<Segment>,
+    // Segment ids, in tour order
+    order: Vec<usize>,
+    segment_of: Vec<usize>,
+    // Index of each node in its segment's array
+    index_of: Vec<usize>,
+    group_size: usize,
+}
+
+struct Segment {
+    nodes: Vec<usize>,
+    reversed: bool,
+    rank: usize,
+}
+
+impl TwoLevelTour {
+    pub fn new(indexes: Vec<usize>) -> TwoLevelTour {
+        let group_size = ((indexes.len() as f64).sqrt().ceil() as usize).max(1);
+        TwoLevelTour::build(indexes, group_size)
+    }
+
+    fn build(indexes: Vec<usize>, group_size: usize) -> TwoLevelTour {
+        let count = indexes.len();
+        let mut segment_of = vec![0; count];
+        let mut index_of = vec![0; count];
+        let segments: Vec<Segment> = indexes
+            .chunks(group_size)
+            .enumerate()
+            .map(|(rank, chunk)| {
+                for (index, &node) in chunk.iter().enumerate() {
+                    segment_of[node] = rank;
+                    index_of[node] = index;
+                }
+                Segment {
+                    nodes: chunk.to_vec(),
+                    reversed: false,
+                    rank,
+                }
+            })
+            .collect();
+        TwoLevelTour {
+            order: (0..segments.len()).collect(),
+            segments,
+            segment_of,
+            index_of,
+            group_size,
+        }
+    }
+
+    fn rebuild(&mut self) {
+        let first = self.first_node(self.order[0]);
+        let indexes = self.to_path(first);
+        *self = TwoLevelTour::build(indexes, self.group_size);
+    }
+
+    fn first_node(&self, segment: usize) -> usize {
+        let segment = &self.segments[segment];
+        if segment.reversed {
+            *segment.nodes.last().unwrap()
+        } else {
+            segment.nodes[0]
+        }
+    }
+
+    fn last_node(&self, segment: usize) -> usize {
+        let segment = &self.segments[segment];
+        if segment.reversed {
+            segment.nodes[0]
+        } else {
+            *segment.nodes.last().unwrap()
+        }
+    }
+
+    // Where a node is in tour order: the rank of its segment, then its offset reading it forward
+    fn position(&self, a: usize) -> (usize, usize) {
+        let segment = &self.segments[self.segment_of[a]];
+        let index = self.index_of[a];
+        let offset = if segment.reversed {
+            segment.nodes.len() - 1 - index
+        } else {
+            index
+        };
+        (segment.rank, offset)
+    }
+
+    // Splits a's segment so that a is the first node of its own segment
+    fn split_before(&mut self, a: usize) {
+        let id = self.segment_of[a];
+        let index = self.index_of[a];
+        let segment = &mut self.segments[id];
+        let tail = if segment.reversed {
+            if index + 1 == segment.nodes.len() {
+                return;
+            }
+            let rest = segment.nodes.split_off(index + 1);
+            std::mem::replace(&mut segment.nodes, rest)
+        } else {
+            if index == 0 {
+                return;
+            }
+            segment.nodes.split_off(index)
+        };
+        let reversed = segment.reversed;
+        let rank = segment.rank + 1;
+
+        let new_id = self.segments.len();
+        self.segments.push(Segment {
+            nodes: tail,
+            reversed,
+            rank,
+        });
+        self.order.insert(rank, new_id);
+        for (rank, &segment) in self.order.iter().enumerate().skip(rank) {
+            self.segments[segment].rank = rank;
+        }
+        for id in [id, new_id] {
+            for (index, &node) in self.segments[id].nodes.iter().enumerate() {
+                self.segment_of[node] = id;
+                self.index_of[node] = index;
+            }
+        }
+    }
+
+    // Reverses the order of count segments starting at rank start, wrapping around the end
+    fn reverse_segments(&mut self, start: usize, count: usize) {
+        let segment_count = self.order.len();
+        let rank = |k: usize| (start + k) % segment_count;
+        for k in 0..count / 2 {
+            self.order.swap(rank(k), rank(count - 1 - k));
+        }
+        for k in 0..count {
+            let segment = &mut self.segments[self.order[rank(k)]];
+            segment.reversed = !segment.reversed;
+            segment.rank = rank(k);
+        }
+    }
+}
+
+impl CyclicTour for TwoLevelTour {
+    fn len(&self) -> usize {
+        self.segment_of.len()
+    }
+
+    fn next(&self, a: usize) -> usize {
+        let segment = &self.segments[self.segment_of[a]];
+        let index = self.index_of[a];
+        if segment.reversed {
+            if index > 0 {
+                return segment.nodes[index - 1];
+            }
+        } else if index + 1 < segment.nodes.len() {
+            return segment.nodes[index + 1];
+        }
+        self.first_node(self.order[(segment.rank + 1) % self.order.len()])
+    }
+
+    fn prev(&self, a: usize) -> usize {
+        let segment = &self.segments[self.segment_of[a]];
+        let index = self.index_of[a];
+        if segment.reversed {
+            if index + 1 < segment.nodes.len() {
+                return segment.nodes[index + 1];
+            }
+        } else if index > 0 {
+            return segment.nodes[index - 1];
+        }
+        let segment_count = self.order.len();
+        self.last_node(self.order[(segment.rank + segment_count - 1) % segment_count])
+    }
+
+    fn between(&self, a: usize, b: usize, c: usize) -> bool {
+        between_positions(self.position(a), self.position(b), self.position(c))
+    }
+
+    fn flip(&mut self, a: usize, b: usize) {
+        // Reversing a single node, or the whole tour, leaves the cycle as it was
+        if a == b || self.next(b) == a {
+            return;
+        }
+        self.split_before(a);
+        self.split_before(self.next(b));
+        let segment_count = self.order.len();
+        let start = self.segments[self.segment_of[a]].rank;
+        let end = self.segments[self.segment_of[b]].rank;
+        let inside = (end + segment_count - start) % segment_count + 1;
+        if inside <= segment_count - inside {
+            self.reverse_segments(start, inside);
+        } else {
+            self.reverse_segments((end + 1) % segment_count, segment_count - inside);
+        }
+        if self.order.len() > 2 * self.len().div_ceil(self.group_size) + 2 {
+            self.rebuild();
+        }
+    }
+
+    fn to_indices(self) -> Vec<usize> {
+        let first = self.first_node(self.order[0]);
+        self.to_path(first)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use crate::testutils::*;
+    use crate::tour::Tour;
+    use crate::twolevel::*;
+
+    // The set of undirected edges in the tour, which two tours share if they're the same cycle
+    fn edges<T: CyclicTour>(tour: &T) -> Vec<(usize, usize)> {
+        let mut edges: Vec<(usize, usize)> = (0..tour.len())
+            .map(|a| (a.min(tour.next(a)), a.max(tour.next(a))))
+            .collect();
+        edges.sort();
+        edges
+    }
+
+    proptest! {
+        #[test]
+        fn flips_match_array_tour(
+            length in 3usize..80,
+            flips in prop::collection::vec((0usize..80, 0usize..80), 1..60)
+        ) {
+            let mut array = Tour::new((0..length).collect());
+            let mut two_level = TwoLevelTour::new((0..length).collect());
+            for (a, b) in flips {
+                let (a, b) = (a % length, b % length);
+                // Both are the same cycle, but may run in opposite directions, in which case the
+                // path from a to b in one is the path from b to a in the other
+                if two_level.next(0) == array.next(0) {
+                    two_level.flip(a, b);
+                } else {
+                    two_level.flip(b, a);
+                }
+                array.flip(a, b);
+                prop_assert_eq!(edges(&array), edges(&two_level));
+                for node in 0..length {
+                    prop_assert_eq!(two_level.prev(two_level.next(node)), node);
+                }
+            }
+            let path = two_level.to_path(0);
+            check_permutation(&path, length - 1);
+        }
+
+        #[test]
+        fn between_follows_tour_order(
+            length in 3usize..60,
+            flips in prop::collection::vec((0usize..60, 0usize..60), 0..20),
+            a in 0usize..60,
+            c in 0usize..60
+        ) {
+            let mut two_level = TwoLevelTour::new((0..length).collect());
+            for (x, y) in flips {
+                two_level.flip(x % length, y % length);
+            }
+            let (a, c) = (a % length, c % length);
+            let path = two_level.to_path(a);
+            let end = path.iter().position(|&n| n == c).unwrap();
+            for (offset, &b) in path.iter().enumerate() {
+                prop_assert_eq!(offset <= end, two_level.between(a, b, c));
+            }
+        }
+    }
+
+    #[test]
+    fn flip_reverses_path() {
+        let mut tour = TwoLevelTour::new((0..9).collect());
+        tour.flip(2, 6);
+        assert_eq!(
+            edges(&Tour::new(vec![0, 1, 6, 5, 4, 3, 2, 7, 8])),
+            edges(&tour)
+        );
+        tour.flip(tour.next(1), tour.prev(0));
+        check_permutation(&tour.to_indices(), 8);
+    }
+}
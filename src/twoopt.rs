@@ -1,13 +1,17 @@
 use rand::thread_rng;
 use rand::Rng;
 
-use crate::tour::Tour;
-use crate::tsp::Tsp;
+use crate::oropt::or_opt_path;
+use crate::tour::{positions, CyclicTour, Tour};
+use crate::tsp::{Tsp, TspInstance};
 use crate::utils;
 use num_traits::int::PrimInt;
 
 const MOD_COUNT: usize = 20;
 
+// Debug builds check the running tour length against a full recomputation this often, in moves
+const VERIFY_INTERVAL: usize = 64;
+
 pub const MINIMUM_ITEMS: usize = 3;
 
 pub fn optimize_twoopt<T: PrimInt>(tsp: &Tsp<T>) -> Result<Vec<usize>, String> {
@@ -28,34 +32,30 @@ fn optimize_twoopt_from_index<T: PrimInt>(
     optimize_twoopt_from_tour(tsp, tour)
 }
 
-pub fn optimize_twoopt_from_tour<T: PrimInt>(
-    tsp: &Tsp<T>,
-    mut tour: Tour,
+pub fn optimize_twoopt_from_tour<I: TspInstance, T: CyclicTour>(
+    tsp: &I,
+    mut tour: T,
 ) -> Result<Vec<usize>, String> {
     let mut improvement_count = 0;
-    let mut distance = tsp.calculate_distance_tour(&tour);
-    let original_distance = distance;
+    let mut move_count = 0;
+    let original_distance = tsp.calculate_distance_tour(&tour);
+    // Kept up to date from the gain of each move, since recomputing it is O(n)
+    let mut distance = original_distance;
 
     loop {
         if improvement_count % MOD_COUNT == 0 {
             utils::perf_trace("Improve TwoOpt", "Optimize", "B", utils::get_micros());
         }
         let mut improved = false;
-        for i in 0..tsp.count {
-            match improve(&tsp, &mut tour, i)? {
-                true => {
-                    let new_distance = tsp.calculate_distance_tour(&tour);
-                    // TODO: remove distance check, once tests are passing
-                    // println!("Update. Tour={:?}, new_distance={}, distance={}", &tour, new_distance, distance);
-                    assert!(new_distance <= distance);
-                    // TODO: this is a hack, we should be able to avoid this by construction
-                    if new_distance < distance {
-                        distance = new_distance;
-                        improved = true;
-                    }
-                    // log::info!("Distance={}, NewDistance={}")
+        for i in 0..tsp.count() {
+            // Every move improve() makes is strictly shorter, so the tour can't cycle
+            if let Some(gain) = improve(tsp, &mut tour, i)? {
+                distance -= gain;
+                move_count += 1;
+                improved = true;
+                if move_count % VERIFY_INTERVAL == 0 {
+                    verify_distance(tsp, &tour, distance);
                 }
-                false => {}
             }
         }
         improvement_count += 1;
@@ -67,47 +67,220 @@ pub fn optimize_twoopt_from_tour<T: PrimInt>(
             if improvement_count % MOD_COUNT != 0 {
                 utils::perf_trace("Improve TwoOpt", "Optimize", "E", utils::get_micros());
             }
-            log::debug!("StartingDistance={}, EndingDistance={}", original_distance, tsp.calculate_distance_tour(&tour));
+            verify_distance(tsp, &tour, distance);
+            log::debug!(
+                "StartingDistance={}, EndingDistance={}, Moves={}",
+                original_distance,
+                distance,
+                move_count
+            );
             return Ok(tour.to_indices());
         }
     }
 }
 
-fn improve<T: PrimInt>(tsp: &Tsp<T>, tour: &mut Tour, base: usize) -> Result<bool, String> {
-    let next_base = tour.next(base);
-    let edge_distance = tsp.retrieve_distance(base, next_base);
-    // TODO: measure time/efficiency tradeoff of doing neighors vs. all vertexes
-    for candidate in &tsp.neighbors[base] {
-        if tour.are_neighbors(base, *candidate) {
-            continue;
+// Cross-checks the incrementally tracked length of the tour. Only debug builds pay for this.
+fn verify_distance<I: TspInstance, T: CyclicTour>(tsp: &I, tour: &T, distance: u64) {
+    if cfg!(debug_assertions) {
+        assert_eq!(
+            tsp.calculate_distance_tour(tour),
+            distance,
+            "Tracked tour length drifted from the actual length"
+        );
+    }
+}
+
+/// Optimizes the tour as a cycle, then cuts it open and keeps improving it as a path with 2-opt
+/// and Or-opt, since an archive has a first and last file and never pays for the edge between
+/// them. If `start` or `end` are given, those nodes are pinned to the respective ends of the path.
+pub fn optimize_twoopt_path<I: TspInstance, T: CyclicTour>(
+    tsp: &I,
+    tour: T,
+    start: Option<usize>,
+    end: Option<usize>,
+) -> Result<Vec<usize>, String> {
+    if let (Some(first), Some(last)) = (start, end) {
+        if first == last {
+            return Err(format!(
+                "Cannot pin node {} to both ends of the path",
+                first
+            ));
+        }
+    }
+    let cycle = Tour::new(optimize_twoopt_from_tour(tsp, tour)?);
+    let path = cut_tour(tsp, &cycle, start, end);
+    let cycle_distance = tsp.calculate_distance_tour(&cycle);
+    let mut path = improve_path(tsp, path, start.is_some(), end.is_some());
+    while or_opt_path(tsp, &mut path, start.is_some(), end.is_some(), true) {
+        path = improve_path(tsp, path, start.is_some(), end.is_some());
+    }
+    log::debug!(
+        "CycleDistance={}, PathDistance={}",
+        cycle_distance,
+        tsp.calculate_distance(&path)
+    );
+    Ok(path)
+}
+
+// Picks where to open the cycle. Without pinned nodes we drop the longest edge, otherwise we drop
+// the longer of the two edges touching the pinned node.
+fn cut_tour<I: TspInstance>(
+    tsp: &I,
+    tour: &Tour,
+    start: Option<usize>,
+    end: Option<usize>,
+) -> Vec<usize> {
+    let mut path = match (start, end) {
+        (Some(first), _) => {
+            if tsp.retrieve_distance(tour.prev(first), first)
+                >= tsp.retrieve_distance(first, tour.next(first))
+            {
+                tour.to_path(first)
+            } else {
+                let mut path = tour.to_path(tour.next(first));
+                path.reverse();
+                path
+            }
+        }
+        (None, Some(last)) => {
+            if tsp.retrieve_distance(last, tour.next(last))
+                >= tsp.retrieve_distance(tour.prev(last), last)
+            {
+                tour.to_path(tour.next(last))
+            } else {
+                let mut path = tour.to_path(last);
+                path.reverse();
+                path
+            }
+        }
+        (None, None) => {
+            let mut longest = 0;
+            let mut first = 0;
+            for i in 0..tour.len() {
+                let distance = tsp.retrieve_distance(i, tour.next(i));
+                if distance > longest {
+                    longest = distance;
+                    first = tour.next(i);
+                }
+            }
+            tour.to_path(first)
+        }
+    };
+    if let Some(last) = end {
+        if path.last() != Some(&last) {
+            path.retain(|&i| i != last);
+            path.push(last);
         }
-        let new_distance = tsp.retrieve_distance(base, *candidate);
-        for candidate_neighbor in vec![tour.next(*candidate)] {
-            // include tour.prev?
-            if candidate_neighbor == base || candidate_neighbor == next_base {
+    }
+    path
+}
+
+// 2-opt for an open path. Besides the usual segment reversal, a path can reverse its prefix or
+// suffix, which swaps which node sits at the end. Those moves are disabled for pinned ends.
+fn improve_path<I: TspInstance>(
+    tsp: &I,
+    mut path: Vec<usize>,
+    pin_start: bool,
+    pin_end: bool,
+) -> Vec<usize> {
+    let count = path.len();
+    if count < MINIMUM_ITEMS {
+        return path;
+    }
+    let mut positions = positions(&path);
+    let last = count - 1;
+
+    loop {
+        let mut improved = false;
+        for i in 0..last {
+            let a = path[i];
+            let b = path[i + 1];
+            let removed = tsp.retrieve_distance(a, b);
+            if !pin_start && i > 0 && tsp.retrieve_distance(path[0], b) < removed {
+                reverse_path_segment(&mut path, &mut positions, 0, i);
+                improved = true;
+                continue;
+            }
+            if !pin_end && i + 1 < last && tsp.retrieve_distance(a, path[last]) < removed {
+                reverse_path_segment(&mut path, &mut positions, i + 1, last);
+                improved = true;
                 continue;
             }
-            let neighbor_distance = tsp.retrieve_distance(*candidate, candidate_neighbor);
-            let rejoin_distance = tsp.retrieve_distance(candidate_neighbor, next_base);
-            if edge_distance + neighbor_distance > rejoin_distance + new_distance {
-                // println!("--------------------------------");
-                // println!("        Tour={:?}", &tour);
-                // println!("        Current_Distance={}", &tsp.calculate_distance_tour(&tour));
-                // println!("        Base={}, Candidate={}, edge_distance={}, new_distance={}, neighbor_distance={}, rejoin_distance={}",
-                //         base, candidate, edge_distance, new_distance, neighbor_distance, rejoin_distance);
-                // check which one follows...
-                if tour.simple_between(*candidate, base, candidate_neighbor) {
-                    tour.flip(base, candidate_neighbor);
-                } else {
-                    tour.flip(next_base, *candidate);
+            for &candidate in tsp.neighbors(a) {
+                let j = positions[candidate];
+                if j <= i + 1 || j == last {
+                    // j == last is the suffix reversal handled above
+                    continue;
+                }
+                let candidate_next = path[j + 1];
+                let old_distance = removed + tsp.retrieve_distance(candidate, candidate_next);
+                let new_distance =
+                    tsp.retrieve_distance(a, candidate) + tsp.retrieve_distance(b, candidate_next);
+                if new_distance < old_distance {
+                    reverse_path_segment(&mut path, &mut positions, i + 1, j);
+                    improved = true;
+                    break;
                 }
-                assert!(tour.are_neighbors(base, *candidate));
-                assert!(tour.are_neighbors(next_base, candidate_neighbor));
-                return Ok(true);
             }
         }
+        if !improved {
+            return path;
+        }
     }
-    Ok(false)
+}
+
+fn reverse_path_segment(path: &mut [usize], positions: &mut [usize], from: usize, to: usize) {
+    path[from..=to].reverse();
+    for (position, &node) in path.iter().enumerate().take(to + 1).skip(from) {
+        positions[node] = position;
+    }
+}
+
+// Looks for a 2-opt move that removes one of the edges at base. For the edge to next_base, the
+// tour runs base, next_base, ..., candidate, next(candidate), and reversing next_base..candidate
+// swaps the two edges for (base, candidate) and (next_base, next(candidate)). The edge to
+// prev_base is the mirror image: the tour runs base, ..., prev(candidate), candidate, ...,
+// prev_base, and reversing candidate..prev_base joins base to candidate. Returns how much shorter
+// the move made the tour.
+fn improve<I: TspInstance, T: CyclicTour>(
+    tsp: &I,
+    tour: &mut T,
+    base: usize,
+) -> Result<Option<u64>, String> {
+    let next_base = tour.next(base);
+    let prev_base = tour.prev(base);
+    let next_distance = tsp.retrieve_distance(base, next_base);
+    let prev_distance = tsp.retrieve_distance(prev_base, base);
+    // TODO: measure time/efficiency tradeoff of doing neighors vs. all vertexes
+    for &candidate in tsp.neighbors(base) {
+        if candidate == base || tour.are_neighbors(base, candidate) {
+            continue;
+        }
+        let new_distance = tsp.retrieve_distance(base, candidate);
+
+        let candidate_next = tour.next(candidate);
+        let removed = next_distance + tsp.retrieve_distance(candidate, candidate_next);
+        let added = new_distance + tsp.retrieve_distance(next_base, candidate_next);
+        if removed > added {
+            debug_assert!(tour.sequence(&[base, next_base, candidate, candidate_next]));
+            tour.flip(next_base, candidate);
+            debug_assert!(tour.are_neighbors(base, candidate));
+            debug_assert!(tour.are_neighbors(next_base, candidate_next));
+            return Ok(Some(removed - added));
+        }
+
+        let candidate_prev = tour.prev(candidate);
+        let removed = prev_distance + tsp.retrieve_distance(candidate_prev, candidate);
+        let added = new_distance + tsp.retrieve_distance(candidate_prev, prev_base);
+        if removed > added {
+            debug_assert!(tour.sequence(&[base, candidate_prev, candidate, prev_base]));
+            tour.flip(candidate, prev_base);
+            debug_assert!(tour.are_neighbors(base, candidate));
+            debug_assert!(tour.are_neighbors(prev_base, candidate_prev));
+            return Ok(Some(removed - added));
+        }
+    }
+    Ok(None)
 }
 
 #[cfg(test)]
@@ -117,6 +290,7 @@ mod tests {
 
     use crate::twoopt::*;
     use crate::testutils::*;
+    use crate::twolevel::TwoLevelTour;
 
     #[test]
     fn test_from_tsp_lib() {
@@ -133,4 +307,63 @@ mod tests {
         let calculated_distance = tsp.calculate_distance(&result);
         assert!(calculated_distance < 8000);
     }
+
+    #[test]
+    fn optimize_twoopt_only_shortens() {
+        let tsp = Tsp::new(build_random_distances(60), 60);
+        let identity = Tour::new((0..60).collect());
+        let starting_distance = tsp.calculate_distance_tour(&identity);
+        let array = optimize_twoopt_from_tour(&tsp, identity).unwrap();
+        let two_level = TwoLevelTour::new((0..60).collect());
+        let two_level = optimize_twoopt_from_tour(&tsp, two_level).unwrap();
+        for result in [array, two_level] {
+            check_permutation(&result, 59);
+            assert!(tsp.calculate_distance_tour(&Tour::new(result)) < starting_distance);
+        }
+    }
+
+    #[test]
+    fn optimize_twoopt_path_is_shorter_than_cycle() {
+        let project_root = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        let file_path = project_root.join("testdata/berlin52.tsp");
+        let coordinates = read_tsp_file(file_path).unwrap().unwrap();
+        let distances: Vec<u32> = build_geometric_distances_alt(coordinates);
+        let tsp = Tsp::new(distances, 52);
+        let cycle = optimize_twoopt_from_index(&tsp, 11).unwrap();
+        let cycle_distance = tsp.calculate_distance_tour(&Tour::new(cycle.clone()));
+        let path = optimize_twoopt_path(&tsp, Tour::new(cycle), None, None).unwrap();
+        check_permutation(&path, 51);
+        assert!(tsp.calculate_distance(&path) < cycle_distance);
+    }
+
+    #[test]
+    fn optimize_twoopt_path_solves_linear_distances() {
+        let distances = build_linear_distances(12);
+        let tsp = Tsp::new(distances, 12);
+        let tour = Tour::new((0..12).rev().collect());
+        let path = optimize_twoopt_path(&tsp, tour, None, None).unwrap();
+        check_permutation(&path, 11);
+        assert_eq!(11, tsp.calculate_distance(&path));
+    }
+
+    #[test]
+    fn optimize_twoopt_path_pins_ends() {
+        let distances = build_linear_distances(12);
+        let tsp = Tsp::new(distances, 12);
+        let tour = Tour::new((0..12).collect());
+        let path = optimize_twoopt_path(&tsp, tour, Some(5), Some(3)).unwrap();
+        check_permutation(&path, 11);
+        assert_eq!(5, path[0]);
+        assert_eq!(3, path[11]);
+
+        let path = optimize_twoopt_path(&tsp, Tour::new((0..12).collect()), None, Some(7)).unwrap();
+        check_permutation(&path, 11);
+        assert_eq!(7, path[11]);
+    }
+
+    #[test]
+    fn optimize_twoopt_path_rejects_same_pinned_ends() {
+        let tsp = Tsp::new(build_linear_distances(5), 5);
+        assert!(optimize_twoopt_path(&tsp, Tour::new((0..5).collect()), Some(2), Some(2)).is_err());
+    }
 }
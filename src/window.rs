@@ -0,0 +1,282 @@
+use crate::tour::positions;
+use crate::tsp::TspInstance;
+
+// However big the window, a file is only compared against this many files before it. Small files
+// could otherwise put thousands of files in the window of each file.
+const WINDOW_FILES: usize = 32;
+// Files are only moved this many positions at a time, which bounds the cost of applying a move
+const MAX_SHIFT: usize = 32;
+const MAX_PASSES: usize = 20;
+/// Each move rescores a few thousand pairs of files, which takes about ten seconds for a full tsp
+/// batch, and adds up to hours on the larger batches sparse-tsp allows, where every pair is a
+/// minhash comparison. Larger paths are left as they are.
+pub const WINDOW_FILE_LIMIT: usize = 14000;
+
+/**
+ * Scores an ordering the way a compressor with a window of `window` bytes sees it. Each file is
+ * charged its distance to the most similar file still in the window when it starts, which is
+ * any earlier file with fewer than `window` bytes of other files between them. Tour length only
+ * charges the distance to the file immediately before, which is what a small window like gzip's
+ * sees once files are larger than it. With xz or zstd --long, a file can be compressed against a
+ * similar file a few megabytes back, so a small unrelated file wedged between two similar ones
+ * costs nothing.
+ */
+pub struct WindowObjective<'a, I: TspInstance> {
+    tsp: &'a I,
+    sizes: &'a [u64],
+    window: u64,
+}
+
+impl<'a, I: TspInstance> WindowObjective<'a, I> {
+    pub fn new(tsp: &'a I, sizes: &'a [u64], window: u64) -> WindowObjective<'a, I> {
+        assert_eq!(tsp.count(), sizes.len());
+        WindowObjective { tsp, sizes, window }
+    }
+
+    pub fn cost(&self, order: &[usize]) -> u64 {
+        (0..order.len()).map(|k| self.cost_at(order, k)).sum()
+    }
+
+    // Distance from the file at position k to the closest file in its window. The first file has
+    // nothing to compare against wherever it is, so it's charged nothing.
+    fn cost_at(&self, order: &[usize], k: usize) -> u64 {
+        let mut best = if k == 0 { 0 } else { u64::MAX };
+        let mut gap = 0;
+        let mut j = k;
+        while j > 0 && k - (j - 1) <= WINDOW_FILES && gap < self.window {
+            j -= 1;
+            best = best.min(self.tsp.retrieve_distance(order[j], order[k]));
+            gap += self.sizes[order[j]];
+        }
+        best
+    }
+
+    // The first position from hi on whose window doesn't reach back to position hi - 1
+    fn reach(&self, order: &[usize], hi: usize) -> usize {
+        let mut gap = 0;
+        let mut k = hi;
+        while k < order.len() && k + 1 - hi <= WINDOW_FILES && gap < self.window {
+            gap += self.sizes[order[k]];
+            k += 1;
+        }
+        k
+    }
+}
+
+/**
+ * Improves an open path under the window objective, by moving single files next to one of their
+ * neighbors, as long as that neighbor is at most MAX_SHIFT positions away. This is a refinement
+ * for an ordering that's already good by tour length: it can shuffle files within a window, but
+ * won't move them across the archive. Pinned ends of the path never move. Paths of more than
+ * WINDOW_FILE_LIMIT files are returned as they are.
+ */
+pub fn optimize_window<I: TspInstance>(
+    tsp: &I,
+    sizes: &[u64],
+    window: u64,
+    mut path: Vec<usize>,
+    pin_start: bool,
+    pin_end: bool,
+) -> Vec<usize> {
+    let count = path.len();
+    if count < 3 {
+        return path;
+    }
+    if count > WINDOW_FILE_LIMIT {
+        log::warn!(
+            "Keeping the path for {} files, which is more than the window objective handles ({})",
+            count,
+            WINDOW_FILE_LIMIT
+        );
+        return path;
+    }
+    let objective = WindowObjective::new(tsp, sizes, window);
+    // The cost of the file at each position, so a move only rescores the positions it changes
+    let mut costs: Vec<u64> = (0..count).map(|k| objective.cost_at(&path, k)).collect();
+    let original_cost: u64 = costs.iter().sum();
+    let mut positions = positions(&path);
+    let movable =
+        |position: usize| (!pin_start || position > 0) && (!pin_end || position + 1 < count);
+    for _ in 0..MAX_PASSES {
+        let mut improved = false;
+        for node in 0..count {
+            let from = positions[node];
+            if !movable(from) {
+                continue;
+            }
+            for &neighbor in tsp.neighbors(node) {
+                let at = positions[neighbor];
+                if neighbor == node || at.abs_diff(from) > MAX_SHIFT {
+                    continue;
+                }
+                // Where the node lands to end up just before or just after the neighbor, once
+                // it's been taken out of the path
+                let targets = if at > from {
+                    [at - 1, at]
+                } else {
+                    [at, at + 1]
+                };
+                let accepted = targets.into_iter().any(|to| {
+                    to != from
+                        && movable(to)
+                        && try_move(&objective, &mut path, &mut costs, &mut positions, from, to)
+                });
+                if accepted {
+                    improved = true;
+                    break;
+                }
+            }
+        }
+        if !improved {
+            break;
+        }
+    }
+    log::debug!(
+        "WindowCost={}, OptimizedWindowCost={}",
+        original_cost,
+        costs.iter().sum::<u64>()
+    );
+    path
+}
+
+// Moves the node at position from to position to, if that lowers the cost, and returns whether
+// it did. Only the files between the two positions change places, so only they and the files
+// whose windows reach back into them can change cost.
+fn try_move<I: TspInstance>(
+    objective: &WindowObjective<I>,
+    path: &mut [usize],
+    costs: &mut [u64],
+    positions: &mut [usize],
+    from: usize,
+    to: usize,
+) -> bool {
+    let lo = from.min(to);
+    let hi = from.max(to) + 1;
+    let end = objective.reach(path, hi);
+    let before: u64 = costs[lo..end].iter().sum();
+    let rotate = |path: &mut [usize], forward: bool| {
+        if (from < to) == forward {
+            path[lo..hi].rotate_left(1);
+        } else {
+            path[lo..hi].rotate_right(1);
+        }
+    };
+    rotate(path, true);
+    let after: Vec<u64> = (lo..end).map(|k| objective.cost_at(path, k)).collect();
+    if after.iter().sum::<u64>() < before {
+        costs[lo..end].copy_from_slice(&after);
+        for (position, &node) in path.iter().enumerate().take(hi).skip(lo) {
+            positions[node] = position;
+        }
+        true
+    } else {
+        rotate(path, false);
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testutils::*;
+    use crate::tsp::{Tsp, TspInstance};
+    use crate::window::*;
+
+    // Files 0 and 2 are nearly the same, and everything else is unrelated
+    fn build_similar_pair() -> Tsp<u8> {
+        let mut distances = vec![200; 16];
+        for i in 0..4 {
+            distances[i * 4 + i] = 0;
+        }
+        distances[2] = 0;
+        distances[8] = 0;
+        Tsp::new(distances, 4)
+    }
+
+    #[test]
+    fn small_windows_score_adjacent_files() {
+        let tsp = Tsp::new(build_random_distances(20), 20);
+        let sizes = vec![100; 20];
+        let order: Vec<usize> = (0..20).collect();
+        let objective = WindowObjective::new(&tsp, &sizes, 1);
+        assert_eq!(tsp.calculate_distance(&order), objective.cost(&order));
+    }
+
+    #[test]
+    fn window_sees_past_small_files() {
+        let tsp = build_similar_pair();
+        let sizes = vec![1000, 10, 1000, 1000];
+        let order = vec![0, 1, 2, 3];
+        assert_eq!(600, WindowObjective::new(&tsp, &sizes, 1).cost(&order));
+        // File 2 still sees file 0 past file 1, but file 3 can't see back to file 1
+        assert_eq!(400, WindowObjective::new(&tsp, &sizes, 100).cost(&order));
+        assert_eq!(400, WindowObjective::new(&tsp, &sizes, 10000).cost(&order));
+    }
+
+    #[test]
+    fn optimize_window_never_increases_cost() {
+        let tsp = Tsp::new(build_random_distances(60), 60);
+        let sizes: Vec<u64> = (0..60).map(|i| (i * 37 % 11 + 1) * 100).collect();
+        let path: Vec<usize> = (0..60).collect();
+        for window in [1, 1000, 100000] {
+            let objective = WindowObjective::new(&tsp, &sizes, window);
+            let optimized = optimize_window(&tsp, &sizes, window, path.clone(), true, true);
+            check_permutation(&optimized, 59);
+            assert!(objective.cost(&optimized) < objective.cost(&path));
+            assert_eq!(0, optimized[0]);
+            assert_eq!(59, optimized[59]);
+        }
+    }
+
+    // Files on a line, each a neighbor of the files on either side, cheap to build at any size
+    struct Line {
+        neighbors: Vec<Vec<usize>>,
+    }
+
+    impl TspInstance for Line {
+        fn count(&self) -> usize {
+            self.neighbors.len()
+        }
+
+        fn retrieve_distance(&self, t1: usize, t2: usize) -> u64 {
+            t1.abs_diff(t2) as u64
+        }
+
+        fn neighbors(&self, index: usize) -> &[usize] {
+            &self.neighbors[index]
+        }
+    }
+
+    // Swaps each pair of neighboring files, which leaves the window objective room to improve
+    fn swapped_line(count: usize) -> (Line, Vec<usize>) {
+        let neighbors = (0..count)
+            .map(|i| {
+                [i.wrapping_sub(1), i + 1]
+                    .into_iter()
+                    .filter(|&j| j < count)
+                    .collect()
+            })
+            .collect();
+        let path = (0..count)
+            .map(|i| if i + 1 < count { i ^ 1 } else { i })
+            .collect();
+        (Line { neighbors }, path)
+    }
+
+    #[test]
+    fn large_paths_are_left_alone() {
+        let (line, path) = swapped_line(100);
+        let sizes = vec![100; 100];
+        assert_ne!(
+            path,
+            optimize_window(&line, &sizes, 1000, path.clone(), false, false)
+        );
+
+        let count = WINDOW_FILE_LIMIT + 1;
+        let (line, path) = swapped_line(count);
+        let sizes = vec![100; count];
+        assert_eq!(
+            path,
+            optimize_window(&line, &sizes, 1000, path.clone(), false, false)
+        );
+    }
+}